@@ -0,0 +1,235 @@
+use async_trait::async_trait;
+use futures::Stream;
+use reqwest::{header::CONTENT_TYPE, Client};
+use serde::Deserialize;
+use serde_json::{json, Value};
+use tokio::sync::mpsc::UnboundedSender;
+use tokio_util::sync::CancellationToken;
+
+use crate::config::{GenerationParams, Profile};
+use crate::error::LLMError;
+use crate::event::{Event, StreamId};
+use crate::llm::*;
+use crate::sse;
+use crate::tool::ToolSpec;
+
+const API_VERSION: &str = "2023-06-01";
+/// the messages api insists on a limit
+const DEFAULT_MAX_TOKENS: u32 = 4096;
+
+// The messages api streams typed sse events:
+// ```
+// event: message_start
+// data: {"type":"message_start","message":{"id":"msg_01","role":"assistant","content":[],"usage":{"input_tokens":14,"output_tokens":1}}}
+//
+// event: content_block_delta
+// data: {"type":"content_block_delta","index":0,"delta":{"type":"text_delta","text":"Hello"}}
+//
+// event: message_delta
+// data: {"type":"message_delta","delta":{"stop_reason":"end_turn","stop_sequence":null},"usage":{"output_tokens":9}}
+//
+// event: message_stop
+// data: {"type":"message_stop"}
+// ```
+// `ping`, `content_block_start` and `content_block_stop` carry nothing for us.
+#[derive(Debug, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+enum StreamEvent {
+    MessageStart {
+        message: MessageStart,
+    },
+    ContentBlockDelta {
+        delta: Delta,
+    },
+    MessageDelta {
+        delta: MessageDelta,
+        usage: Option<TokenCounts>,
+    },
+    MessageStop,
+    Error {
+        error: ApiError,
+    },
+    #[serde(other)]
+    Other,
+}
+
+#[derive(Debug, Deserialize)]
+struct MessageStart {
+    usage: Option<TokenCounts>,
+}
+
+/// text deltas carry `text`, other block types are skipped
+#[derive(Debug, Deserialize)]
+struct Delta {
+    text: Option<String>,
+}
+
+#[derive(Debug, Deserialize)]
+struct MessageDelta {
+    stop_reason: Option<String>,
+}
+
+#[derive(Debug, Default, Deserialize)]
+#[serde(default)]
+struct TokenCounts {
+    input_tokens: u64,
+    cache_creation_input_tokens: u64,
+    cache_read_input_tokens: u64,
+    output_tokens: u64,
+}
+
+#[derive(Debug, Deserialize)]
+struct ApiError {
+    #[serde(rename = "type")]
+    kind: String,
+    message: String,
+}
+
+#[derive(Debug)]
+pub struct Anthropic {
+    cli: Client,
+    profile: Profile,
+}
+
+impl Anthropic {
+    pub fn new(profile: Profile) -> Self {
+        Self {
+            cli: http_client(),
+            profile,
+        }
+    }
+
+    /// system messages go to the top level `system` field, only user and
+    /// assistant turns are left in `messages`
+    pub(crate) fn body(&self, history: &[Message], params: &GenerationParams) -> Value {
+        let system = history
+            .iter()
+            .filter(|msg| msg.is_system())
+            .filter_map(|msg| msg.content.as_deref())
+            .collect::<Vec<_>>();
+        let messages = history
+            .iter()
+            .filter(|msg| !msg.is_system())
+            .map(|msg| json!({ "role": msg.role, "content": msg.content }))
+            .collect::<Vec<_>>();
+
+        let mut data = json!({
+            "model": self.profile.model,
+            "stream": true,
+            "max_tokens": params.max_tokens.unwrap_or(DEFAULT_MAX_TOKENS),
+            "messages": messages,
+        });
+        if !system.is_empty() {
+            data["system"] = json!(system.join("\n\n"));
+        }
+        // no seed, penalties or json mode in this api
+        let fields = [
+            ("temperature", json!(params.temperature)),
+            ("top_p", json!(params.top_p)),
+            ("stop_sequences", json!(params.stop)),
+        ];
+        for (name, value) in fields {
+            if !value.is_null() {
+                data[name] = value;
+            }
+        }
+        data
+    }
+}
+
+#[async_trait]
+impl LLMService for Anthropic {
+    async fn request(
+        &mut self,
+        id: StreamId,
+        history: Vec<Message>,
+        params: GenerationParams,
+        _tools: Vec<ToolSpec>,
+        tx: UnboundedSender<Event>,
+        cancel: CancellationToken,
+    ) -> Result<(), LLMError> {
+        let endpoint = self.profile.endpoint();
+        let api_key = self.profile.api_key();
+
+        let data = self.body(&history, &params);
+
+        tx.send(Event::LLMEventStart(id)).unwrap();
+
+        let timer = Timer::start();
+        let req = || {
+            self.cli
+                .post(&endpoint)
+                .header("x-api-key", &api_key)
+                .header("anthropic-version", API_VERSION)
+                .header(CONTENT_TYPE, "application/json")
+                .json(&data)
+        };
+        let Some(resp) = send_with_retry(id, &self.profile.retry, req, &tx, &cancel).await? else {
+            tx.send(Event::LLMEventCancelled(id)).unwrap();
+            return Ok(());
+        };
+
+        read_stream(id, resp.bytes_stream(), timer, &tx, &cancel).await
+    }
+}
+
+/// forward the text deltas of a messages api stream, ends like
+/// `chatgpt::read_stream`
+pub(crate) async fn read_stream<S, B, E>(
+    id: StreamId,
+    body: S,
+    timer: Timer,
+    tx: &UnboundedSender<Event>,
+    cancel: &CancellationToken,
+) -> Result<(), LLMError>
+where
+    S: Stream<Item = Result<B, E>>,
+    B: AsRef<[u8]>,
+    E: Into<LLMError>,
+{
+    read_events(id, sse::decode(body), timer, tx, cancel, |event, reply| {
+        if event.data.is_empty() {
+            return Ok(Flow::Continue);
+        }
+        match serde_json::from_str::<StreamEvent>(&event.data)? {
+            StreamEvent::MessageStart { message } => {
+                reply.usage = message.usage.map(|counts| {
+                    let prompt_tokens = counts.input_tokens
+                        + counts.cache_creation_input_tokens
+                        + counts.cache_read_input_tokens;
+                    Usage {
+                        prompt_tokens,
+                        completion_tokens: counts.output_tokens,
+                        total_tokens: prompt_tokens + counts.output_tokens,
+                        ..Default::default()
+                    }
+                });
+            }
+            StreamEvent::ContentBlockDelta { delta } => {
+                if let Some(text) = delta.text.filter(|text| !text.is_empty()) {
+                    reply.timer.token();
+                    tx.send(Event::LLMEventDelta(id, Message::assistant(text)))
+                        .unwrap();
+                }
+            }
+            StreamEvent::MessageDelta {
+                delta,
+                usage: counts,
+            } => {
+                reply.stop_reason = delta.stop_reason.or(reply.stop_reason.take());
+                // the output count is cumulative
+                if let (Some(usage), Some(counts)) = (reply.usage.as_mut(), counts) {
+                    usage.completion_tokens = counts.output_tokens;
+                    usage.total_tokens = usage.prompt_tokens + counts.output_tokens;
+                }
+            }
+            StreamEvent::MessageStop => return Ok(Flow::Done),
+            StreamEvent::Error { error } => {
+                return Err(LLMError::Api(format!("{}: {}", error.kind, error.message)));
+            }
+            StreamEvent::Other => {}
+        }
+        Ok(Flow::Continue)
+    })
+    .await
+}
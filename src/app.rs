@@ -1,45 +1,516 @@
-use crossterm::event::{Event as CrosstermEvent, KeyCode, KeyEvent, KeyEventKind, KeyModifiers};
+use crossterm::event::{
+    Event as CrosstermEvent, KeyCode, KeyEvent, KeyEventKind, KeyModifiers, MouseEventKind,
+};
 use ratatui::backend::Backend;
 use ratatui::buffer::Buffer;
 use ratatui::layout::Size;
 use ratatui::prelude::*;
-use ratatui::widgets::{Block, BorderType, Borders, Paragraph};
+use ratatui::widgets::{
+    Block, BorderType, Borders, Clear, HighlightSpacing, List, ListState, Paragraph, Wrap,
+};
 use ratatui::{layout::Constraint, Frame, Terminal};
-use tokio::sync::Mutex;
+use tokio_util::sync::CancellationToken;
 use tui_scrollview::{ScrollView, ScrollViewState};
 use tui_textarea::TextArea;
 
+use std::collections::{BTreeMap, BTreeSet};
 use std::io::Result;
-use std::sync::Arc;
-use std::u16;
+use std::time::{Duration, Instant};
+
+use crate::attach::{self, Attachment};
+use crate::clipboard;
+use crate::command::{self, Registry};
+use crate::config::{Config, GenerationParams, Persona, Profile};
+use crate::cost::{self, Budget, Ledger, Price};
+use crate::error::LLMError;
+use crate::event::{Event, EventManager, StreamId};
+use crate::llm::{self, LLMProvider, LineCache, Message, Stats, ToolCall};
+use crate::markdown;
+use crate::session::Session;
+use crate::tool::ToolRegistry;
+
+/// tool call rounds answering one prompt, stops a model calling tools forever
+const MAX_TOOL_ROUNDS: usize = 10;
+
+/// popup listing saved sessions
+struct SessionPicker {
+    sessions: Vec<Session>,
+    state: ListState,
+    rename: Option<String>, // new name being typed
+    confirm_delete: bool,
+}
+
+/// an answer being streamed in
+struct Stream {
+    id: StreamId,
+    profile: String,
+    content: String,
+    done: bool,
+    interrupted: bool,
+    error: Option<LLMError>,
+    retry: Option<String>, // why and when the request is retried
+    stats: Option<Stats>,
+    tool_calls: Vec<ToolCall>,
+}
+
+/// the pane receiving keys, cycled with Tab and Shift-Tab
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Focus {
+    Input,
+    Chat,
+}
+
+impl Focus {
+    const ALL: [Focus; 2] = [Focus::Input, Focus::Chat];
+
+    fn next(self) -> Self {
+        let i = Self::ALL.iter().position(|f| *f == self).unwrap_or(0);
+        Self::ALL[(i + 1) % Self::ALL.len()]
+    }
+
+    fn prev(self) -> Self {
+        let i = Self::ALL.iter().position(|f| *f == self).unwrap_or(0);
+        Self::ALL[(i + Self::ALL.len() - 1) % Self::ALL.len()]
+    }
+}
 
-use crate::event::{Event, EventManager};
-use crate::llm::{LLMProvider, LLMService, Message};
 pub struct App<'a> {
     event_manager: EventManager,
     quit: bool,
     last_key: Option<KeyEvent>,
     input: TextArea<'a>,
-    messages: Vec<Message>, // list of completed messages
+    session: Session, // holds the list of completed messages
     notification: Option<String>,
-    current_message: Option<String>, // current message on the fly
-    llm: Arc<Mutex<Box<dyn LLMService + 'static>>>,
+    streams: Vec<Stream>, // answers on the fly, more than one in parallel mode
+    next_stream_id: StreamId,
+    cancel: Option<CancellationToken>, // stops the streams of the active prompt
+    provider: LLMProvider,
+    profile_picker: Option<ListState>, // popup to switch the active profile
+    session_picker: Option<SessionPicker>,
     scroll_view_state: ScrollViewState,
+    follow: bool,     // keep the chat scrolled to the bottom as content arrives
+    chat_height: u16, // visible rows of the chat when last rendered
+    scroll_max: u16,  // offset that shows the bottom of the chat
+    focus: Focus,
+    selected: Option<usize>, // message selected in the chat, index into the session
+    reveal_selected: bool,   // scroll the selected message into view on next render
+    copied_block: Option<(usize, usize)>, // message and code block copied last, `c` moves on
+    flash: Option<(String, Instant)>, // short lived status, e.g. what was copied
+    editing: Option<usize>,  // earlier prompt being edited, resending it starts a branch
+    answer_at: Option<usize>, // where the answers being streamed go in the conversation
+    personas: BTreeMap<String, Persona>,
+    default_persona: Option<String>, // persona of new sessions
+    params: GenerationParams,        // set with `/set`, override those of the profiles
+    prices: BTreeMap<String, Price>,
+    budget: Budget,
+    ledger: Ledger, // dollars spent per day across sessions
+    help: bool,     // the `/help` popup is open
+    tools: ToolRegistry,
+    tool_queue: Vec<ToolCall>, // calls of the last answer not run yet
+    confirm_tool: bool,        // asking whether to run the first queued call
+    tool_running: bool,
+    tool_rounds: usize,              // tool call answers to the current prompt
+    allowed_tools: BTreeSet<String>, // run without asking for the rest of the run
+    attachments: Vec<Attachment>,    // sent with the next prompt
+    lines: LineCache,                // rendered messages, reused across frames
+    mention_matches: Option<(String, Vec<String>)>, // paths completing the `@` mention typed
 }
 
 impl<'a> App<'a> {
-    pub fn new() -> Self {
-        Self {
+    pub fn new(config: Config) -> Self {
+        let mut app = Self {
             event_manager: EventManager::new(),
             quit: false,
             last_key: None,
             input: TextArea::default(),
-            messages: Vec::default(),
+            session: Session::new(),
             notification: None,
-            current_message: None,
-            llm: Arc::new(Mutex::new(LLMProvider::new())),
+            streams: Vec::new(),
+            next_stream_id: 0,
+            cancel: None,
+            provider: LLMProvider::new(&config),
+            profile_picker: None,
+            session_picker: None,
             scroll_view_state: ScrollViewState::default(),
+            follow: true,
+            chat_height: 0,
+            scroll_max: 0,
+            focus: Focus::Input,
+            selected: None,
+            reveal_selected: false,
+            copied_block: None,
+            flash: None,
+            editing: None,
+            answer_at: None,
+            personas: config.personas,
+            default_persona: config.persona,
+            params: GenerationParams::default(),
+            prices: config.prices,
+            budget: config.budget,
+            ledger: Ledger::load(),
+            help: false,
+            tools: ToolRegistry::builtin().only(&config.tools),
+            tool_queue: Vec::new(),
+            confirm_tool: false,
+            tool_running: false,
+            tool_rounds: 0,
+            allowed_tools: BTreeSet::new(),
+            attachments: Vec::new(),
+            lines: LineCache::default(),
+            mention_matches: None,
+        };
+        app.session = app.new_session();
+        app
+    }
+
+    /// an empty session with the default persona
+    fn new_session(&self) -> Session {
+        let mut session = Session::new();
+        if let Some(persona) = self
+            .default_persona
+            .as_ref()
+            .and_then(|p| self.personas.get(p))
+        {
+            session.system = Some(persona.prompt.clone());
+            session.persona = Some(persona.name.clone());
+        }
+        session
+    }
+
+    /// the slash commands, in the order `/help` lists them
+    fn commands() -> Registry<Self> {
+        Registry::<Self>::new()
+            .command("help", "", "list the commands", |app, _| {
+                app.help = true;
+                String::new()
+            })
+            .command("clear", "", "start a new session", |app, _| {
+                app.open_session(app.new_session());
+                "new session".to_owned()
+            })
+            .command(
+                "model",
+                "[profile]",
+                "switch the profile, list them without one",
+                Self::cmd_model,
+            )
+            .complete(|app, _| {
+                app.provider
+                    .profiles()
+                    .iter()
+                    .map(|p| p.name.clone())
+                    .collect()
+            })
+            .command(
+                "save",
+                "[name]",
+                "save the session, renaming it with a name",
+                Self::cmd_save,
+            )
+            .command(
+                "load",
+                "<session>",
+                "open a saved session by id or name",
+                Self::cmd_load,
+            )
+            .complete(|_, _| {
+                Session::list()
+                    .unwrap_or_default()
+                    .into_iter()
+                    .map(|s| s.id)
+                    .collect()
+            })
+            .command(
+                "persona",
+                "[name|none]",
+                "switch the system prompt to a persona",
+                Self::cmd_persona,
+            )
+            .complete(|app, _| {
+                let mut names = app.personas.keys().cloned().collect::<Vec<_>>();
+                names.push("none".to_owned());
+                names
+            })
+            .command(
+                "system",
+                "[prompt]",
+                "set the system prompt, clear it without one",
+                Self::cmd_system,
+            )
+            .command(
+                "set",
+                "[name value]",
+                "set a parameter, list them without one",
+                Self::cmd_set,
+            )
+            .complete(|_, _| {
+                GenerationParams::NAMES
+                    .iter()
+                    .map(|s| s.to_string())
+                    .collect()
+            })
+            .command(
+                "unset",
+                "<name>",
+                "drop a parameter set with /set",
+                |app, arg| match app.params.unset(arg) {
+                    Ok(()) => format!("{} unset", arg),
+                    Err(e) => e,
+                },
+            )
+            .complete(|_, _| {
+                GenerationParams::NAMES
+                    .iter()
+                    .map(|s| s.to_string())
+                    .collect()
+            })
+            .command(
+                "cost",
+                "",
+                "what the session and today cost",
+                Self::cmd_cost,
+            )
+            .command(
+                "attach",
+                "[glob]",
+                "send files with the next prompt, list them without one",
+                Self::cmd_attach,
+            )
+            .complete(|_, arg| attach::complete(arg))
+            .command(
+                "detach",
+                "[path]",
+                "drop an attached file, all without one",
+                Self::cmd_detach,
+            )
+            .complete(|app, _| app.attachments.iter().map(|a| a.path.clone()).collect())
+            .command("quit", "", "save and quit", |app, _| {
+                app.quit = true;
+                String::new()
+            })
+    }
+
+    /// run `prompt` if it is a slash command, false for any other prompt.
+    /// an unknown command stays in the input to be fixed or escaped.
+    fn process_command(&mut self, prompt: &str) -> bool {
+        let commands = Self::commands();
+        let Some(status) = commands.dispatch(self, prompt) else {
+            return false;
+        };
+        if !status.is_empty() {
+            self.flash = Some((status, Instant::now()));
+        }
+        if command::parse(prompt).is_some_and(|(name, _)| commands.get(name).is_some()) {
+            self.clear();
+            self.save_session();
+        }
+        true
+    }
+
+    /// complete the command or argument being typed, a list of the
+    /// candidates is shown if there is more than one
+    fn complete_command(&mut self) {
+        let input = self.input.lines().join("\n");
+        let candidates = Self::commands().completions(self, &input);
+        let completed = command::common_prefix(&candidates);
+        if completed.len() > input.len() {
+            self.clear();
+            self.input.insert_str(&completed);
+        }
+        if candidates.len() > 1 {
+            let words = candidates
+                .iter()
+                .filter_map(|c| c.trim_end().rsplit(' ').next())
+                .collect::<Vec<_>>();
+            self.flash = Some((words.join("  "), Instant::now()));
+        }
+    }
+
+    fn cmd_attach(&mut self, arg: &str) -> String {
+        if arg.is_empty() {
+            return match self.attachments.len() {
+                0 => "nothing attached, /attach <glob> or @path in the prompt".to_owned(),
+                n => format!("{} files attached", n),
+            };
+        }
+        let (files, skipped) = match attach::glob(arg) {
+            Ok(found) => found,
+            Err(e) => return e,
+        };
+        let added = files.len();
+        for file in files {
+            self.attachments.retain(|a| a.path != file.path);
+            self.attachments.push(file);
+        }
+        match skipped.len() {
+            0 => format!("attached {} files", added),
+            _ => format!("attached {} files, skipped {}", added, skipped.join(", ")),
+        }
+    }
+
+    fn cmd_detach(&mut self, arg: &str) -> String {
+        if arg.is_empty() {
+            self.attachments.clear();
+            return "attachments dropped".to_owned();
+        }
+        let before = self.attachments.len();
+        self.attachments.retain(|a| a.path != arg);
+        if self.attachments.len() == before {
+            format!("{} is not attached", arg)
+        } else {
+            format!("dropped {}", arg)
+        }
+    }
+
+    /// the `@path` being typed before the cursor, without the `@`
+    fn mention(&self) -> Option<String> {
+        let (row, col) = self.input.cursor();
+        let line = self.input.lines().get(row)?;
+        let before = line.chars().take(col).collect::<String>();
+        let word = before.rsplit(char::is_whitespace).next()?;
+        word.strip_prefix('@').map(String::from)
+    }
+
+    /// paths completing the mention `partial`, the directory is only read
+    /// again once it changed and not for every frame
+    fn mention_completions(&mut self, partial: &str) -> Vec<String> {
+        match self.mention_matches {
+            Some((ref typed, ref matches)) if typed == partial => matches.clone(),
+            _ => {
+                let matches = attach::complete(partial);
+                self.mention_matches = Some((partial.to_owned(), matches.clone()));
+                matches
+            }
+        }
+    }
+
+    /// complete the path of the `@` mention at the cursor
+    fn complete_mention(&mut self) {
+        let Some(partial) = self.mention() else {
+            return;
+        };
+        let candidates = self.mention_completions(&partial);
+        let completed = command::common_prefix(&candidates);
+        if completed.len() > partial.len() {
+            self.input.insert_str(&completed[partial.len()..]);
+        }
+        // a single file is done, go on with the prompt
+        if let [file] = &candidates[..] {
+            if !file.ends_with('/') {
+                self.input.insert_char(' ');
+            }
+        }
+    }
+
+    fn cmd_model(&mut self, arg: &str) -> String {
+        if arg.is_empty() {
+            let names = self
+                .provider
+                .profiles()
+                .iter()
+                .map(|p| format!("{} ({})", p.name, p.model))
+                .collect::<Vec<_>>();
+            return format!("profiles: {}", names.join(", "));
+        }
+        if self.provider.switch_by_name(arg) {
+            format!("profile {}", arg)
+        } else {
+            format!("unknown profile {}", arg)
+        }
+    }
+
+    fn cmd_save(&mut self, arg: &str) -> String {
+        if !arg.is_empty() {
+            self.session.name = arg.to_owned();
+        }
+        if self.session.conversation.is_empty() {
+            return "nothing to save yet".to_owned();
+        }
+        match self.session.save() {
+            Ok(()) => format!("saved {}", self.session.title()),
+            Err(e) => format!("failed to save session: {}", e),
+        }
+    }
+
+    fn cmd_load(&mut self, arg: &str) -> String {
+        let session = Session::load(arg).or_else(|e| {
+            Session::list()?
+                .into_iter()
+                .find(|s| s.title().eq_ignore_ascii_case(arg))
+                .ok_or(e)
+        });
+        match session {
+            Ok(session) => {
+                let title = session.title();
+                self.open_session(session);
+                format!("opened {}", title)
+            }
+            Err(e) => format!("no session {}: {}", arg, e),
+        }
+    }
+
+    fn cmd_persona(&mut self, arg: &str) -> String {
+        if arg.is_empty() {
+            let names = self.personas.keys().cloned().collect::<Vec<_>>();
+            return format!("personas: {} (/persona none to clear)", names.join(", "));
+        }
+        if arg == "none" {
+            self.session.system = None;
+            self.session.persona = None;
+            return "system prompt cleared".to_owned();
+        }
+        match self.personas.get(arg) {
+            Some(persona) => {
+                self.session.system = Some(persona.prompt.clone());
+                self.session.persona = Some(persona.name.clone());
+                format!("persona {}", persona.name)
+            }
+            None => format!("unknown persona {}", arg),
+        }
+    }
+
+    fn cmd_system(&mut self, arg: &str) -> String {
+        self.session.system = (!arg.is_empty()).then(|| arg.to_owned());
+        self.session.persona = None;
+        if arg.is_empty() {
+            "system prompt cleared".to_owned()
+        } else {
+            "system prompt set".to_owned()
+        }
+    }
+
+    fn cmd_set(&mut self, arg: &str) -> String {
+        if arg.is_empty() {
+            let active = self.provider.active_index();
+            let params = self.provider.params(active, &self.params).describe();
+            if params.is_empty() {
+                return "no parameters set".to_owned();
+            }
+            return params.join(" ");
+        }
+        let (key, value) = arg.split_once(char::is_whitespace).unwrap_or((arg, ""));
+        match self.params.set(key, value.trim()) {
+            Ok(()) => format!("{} set to {}", key, value.trim()),
+            Err(e) => e,
+        }
+    }
+
+    fn cmd_cost(&mut self, _: &str) -> String {
+        let (prompt, completion) = self.session.tokens();
+        let mut status = format!(
+            "session {} ({}+{} tok) · today {}",
+            cost::dollars(self.session.cost()),
+            prompt,
+            completion,
+            cost::dollars(self.ledger.today())
+        );
+        if let Some(daily) = self.budget.daily {
+            status.push_str(&format!(" of {}", cost::dollars(daily)));
+        }
+        if self.prices.is_empty() {
+            status.push_str(" · no prices configured");
         }
+        status
     }
 
     pub async fn run<B: Backend>(&mut self, term: &mut Terminal<B>) -> Result<()> {
@@ -50,20 +521,59 @@ impl<'a> App<'a> {
                 Ok(Event::TermEvent(ev)) => {
                     self.process_event(ev).await;
                 }
-                Ok(Event::LLMEventDelta(msg)) => {
-                    if let Some(ref delta) = msg.content {
-                        self.current_message
-                            .get_or_insert("".to_string())
-                            .push_str(&delta);
+                Ok(Event::LLMEventDelta(id, msg)) => {
+                    if let Some(stream) = self.stream_mut(id) {
+                        if let Some(ref delta) = msg.content {
+                            stream.content.push_str(delta);
+                        }
+                        if let Some(calls) = msg.tool_calls {
+                            llm::merge_tool_calls(&mut stream.tool_calls, calls);
+                        }
+                        stream.retry = None;
+                    }
+                }
+                Ok(Event::LLMEventRetry(id, status)) => {
+                    if let Some(stream) = self.stream_mut(id) {
+                        stream.retry = Some(status);
+                    }
+                }
+                Ok(Event::LLMEventStats(id, stats)) => {
+                    if let Some(stream) = self.stream_mut(id) {
+                        stream.stats = Some(stats);
+                    }
+                }
+                Ok(Event::LLMEventEnd(id)) => {
+                    if let Some(stream) = self.stream_mut(id) {
+                        stream.done = true;
                     }
+                    self.finish_streams();
                 }
-                Ok(Event::LLMEventEnd) => {
-                    if let Some(msg) = self.current_message.take() {
-                        self.messages.push(Message::assistant(msg));
+                Ok(Event::LLMEventError(id, e)) => {
+                    if let Some(stream) = self.stream_mut(id) {
+                        stream.done = true;
+                        stream.error = Some(e);
                     }
+                    self.finish_streams();
                 }
-                Ok(Event::LLMEventStart) => {
-                    self.current_message.take();
+                Ok(Event::LLMEventCancelled(id)) => {
+                    if let Some(stream) = self.stream_mut(id) {
+                        stream.done = true;
+                        stream.interrupted = true;
+                    }
+                    self.finish_streams();
+                }
+                Ok(Event::LLMEventStart(id)) => {
+                    if let Some(stream) = self.stream_mut(id) {
+                        stream.content.clear();
+                    }
+                }
+                Ok(Event::ToolResult(call, output)) => {
+                    self.tool_running = false;
+                    let at = self.session.conversation.len();
+                    self.session
+                        .conversation
+                        .insert(at, Message::tool(&call, output));
+                    self.next_tool();
                 }
                 Ok(Event::Notification(msg)) => {
                     // self.notification.replace(msg);
@@ -80,9 +590,375 @@ impl<'a> App<'a> {
             }
         }
 
+        self.save_session();
         Ok(())
     }
 
+    fn stream_mut(&mut self, id: StreamId) -> Option<&mut Stream> {
+        self.streams.iter_mut().find(|s| s.id == id)
+    }
+
+    /// once every parallel answer is done, move them into `messages`
+    /// together so they stay on the same row
+    fn finish_streams(&mut self) {
+        if self.streams.iter().any(|s| !s.done) {
+            return;
+        }
+
+        let parallel = self.streams.len() > 1;
+        let mut answers = Vec::new();
+        let mut spent = 0.0;
+        for mut stream in std::mem::take(&mut self.streams) {
+            if let Some(ref mut stats) = stream.stats {
+                stats.cost = self.cost_of(&stream.profile, stats);
+                spent += stats.cost.unwrap_or_default();
+            }
+            let provider = parallel.then(|| stream.profile.clone());
+            let empty = stream.content.is_empty() && stream.tool_calls.is_empty();
+            if !empty || stream.error.is_none() && !stream.interrupted {
+                let mut msg = Message::assistant(stream.content);
+                msg.interrupted = stream.interrupted || stream.error.is_some();
+                msg.provider = provider.clone();
+                msg.stats = stream.stats;
+                // calls of a cut off answer may be incomplete and never run,
+                // unanswered ones would get every later request rejected
+                if !msg.interrupted && !stream.tool_calls.is_empty() {
+                    msg.tool_calls = Some(stream.tool_calls);
+                }
+                answers.push(msg);
+            }
+            if let Some(e) = stream.error {
+                let mut msg = Message::error(e.to_string());
+                msg.provider = provider;
+                answers.push(msg);
+            }
+        }
+        // a regenerated answer goes next to the one it replaces
+        let at = self
+            .answer_at
+            .take()
+            .unwrap_or(self.session.conversation.len());
+        for (i, msg) in answers.into_iter().enumerate() {
+            self.session.conversation.insert(at + i, msg);
+        }
+        self.cancel = None;
+        self.save_session();
+        if spent > 0.0 {
+            if let Err(e) = self.ledger.add(spent) {
+                self.notification = Some(format!("failed to update the ledger: {}", e));
+            }
+        }
+
+        // a complete answer asking for tools, run them and answer again
+        let last = self.session.conversation.messages().pop();
+        if let Some(msg) = last.filter(|m| m.has_tool_calls() && !m.interrupted) {
+            self.tool_queue = msg.tool_calls.unwrap_or_default();
+            self.tool_rounds += 1;
+            if self.tool_rounds > MAX_TOOL_ROUNDS {
+                self.flash = Some((
+                    format!("stopped after {} tool rounds", MAX_TOOL_ROUNDS),
+                    Instant::now(),
+                ));
+                self.decline_tools("not run: tool round limit reached");
+                return;
+            }
+            self.next_tool();
+        }
+    }
+
+    /// run the next queued tool call, or ask first unless it is always
+    /// allowed. with none left the results are sent back to the model.
+    fn next_tool(&mut self) {
+        let Some(call) = self.tool_queue.first() else {
+            self.continue_after_tools();
+            return;
+        };
+        let name = call.function.name.clone().unwrap_or_default();
+        if self.allowed_tools.contains(&name) {
+            self.run_tool();
+        } else {
+            self.confirm_tool = true;
+        }
+    }
+
+    /// run the first queued call off the ui thread, the output comes back
+    /// as `Event::ToolResult`
+    fn run_tool(&mut self) {
+        self.confirm_tool = false;
+        if self.tool_queue.is_empty() {
+            return;
+        }
+        let call = self.tool_queue.remove(0);
+        let tools = self.tools.clone();
+        let tx = self.event_manager.get_sender();
+        self.tool_running = true;
+        tokio::task::spawn_blocking(move || {
+            let name = call.function.name.as_deref().unwrap_or_default();
+            let output = tools.call(name, &call.function.arguments);
+            let _ = tx.send(Event::ToolResult(call, output));
+        });
+    }
+
+    /// answer a declined call with a note, the model has to get one result
+    /// per call
+    fn decline_tool(&mut self) {
+        self.confirm_tool = false;
+        if self.tool_queue.is_empty() {
+            return;
+        }
+        let call = self.tool_queue.remove(0);
+        let at = self.session.conversation.len();
+        let msg = Message::tool(&call, "the user declined to run this".to_owned());
+        self.session.conversation.insert(at, msg);
+        self.next_tool();
+    }
+
+    /// answer every queued call with `note` and don't ask the model again,
+    /// each call needs a result or the next request is rejected
+    fn decline_tools(&mut self, note: &str) {
+        self.confirm_tool = false;
+        for call in std::mem::take(&mut self.tool_queue) {
+            let at = self.session.conversation.len();
+            let msg = Message::tool(&call, note.to_owned());
+            self.session.conversation.insert(at, msg);
+        }
+        self.save_session();
+    }
+
+    fn continue_after_tools(&mut self) {
+        self.save_session();
+        if !self.check_budget() {
+            return;
+        }
+        let len = self.session.conversation.len();
+        self.start_streams(len);
+        self.answer_at = Some(len);
+    }
+
+    /// a tool call is queued, running or waiting for confirmation
+    fn tools_pending(&self) -> bool {
+        self.tool_running || !self.tool_queue.is_empty()
+    }
+
+    /// answers are streaming or tools are about to add to the conversation,
+    /// it must not change under them
+    fn busy(&self) -> bool {
+        !self.streams.is_empty() || self.tools_pending()
+    }
+
+    /// dollars the answer of `profile` cost, if its model has a price
+    fn cost_of(&self, profile: &str, stats: &Stats) -> Option<f64> {
+        let profile = self
+            .provider
+            .profiles()
+            .iter()
+            .find(|p| p.name == profile)?;
+        let price = cost::price_of(&self.prices, &profile.model)?;
+        Some(price.cost(stats.prompt_tokens?, stats.completion_tokens?))
+    }
+
+    /// why no more should be spent, if a budget is exceeded
+    fn over_budget(&self) -> Option<String> {
+        let today = self.ledger.today();
+        let session = self.session.cost();
+        match (self.budget.daily, self.budget.session) {
+            (Some(limit), _) if today >= limit => Some(format!(
+                "daily budget of {} spent ({})",
+                cost::dollars(limit),
+                cost::dollars(today)
+            )),
+            (_, Some(limit)) if session >= limit => Some(format!(
+                "session budget of {} spent ({})",
+                cost::dollars(limit),
+                cost::dollars(session)
+            )),
+            _ => None,
+        }
+    }
+
+    /// false if a budget is exceeded and sending is refused, a warning is
+    /// flashed either way
+    fn check_budget(&mut self) -> bool {
+        let Some(reason) = self.over_budget() else {
+            return true;
+        };
+        if self.budget.refuse {
+            self.flash = Some((format!("not sent, {}", reason), Instant::now()));
+            false
+        } else {
+            self.flash = Some((reason, Instant::now()));
+            true
+        }
+    }
+
+    /// replace the current conversation, e.g. when resuming from the command line
+    pub fn open_session(&mut self, session: Session) {
+        self.save_session();
+        self.session = session;
+        self.scroll_view_state = ScrollViewState::default();
+        self.follow = true;
+        self.selected = None;
+        self.editing = None;
+    }
+
+    fn border_style(&self, pane: Focus) -> Style {
+        if self.focus == pane {
+            Style::new().cyan()
+        } else {
+            Style::new().dark_gray()
+        }
+    }
+
+    /// move the selection in the chat by `delta` messages, starting from
+    /// the last one
+    fn select_by(&mut self, delta: isize) {
+        let count = self.session.conversation.len();
+        if count == 0 {
+            return;
+        }
+        let i = match self.selected {
+            Some(i) => (i as isize + delta).clamp(0, count as isize - 1) as usize,
+            None => count - 1,
+        };
+        self.selected = Some(i);
+        self.reveal_selected = true;
+    }
+
+    /// put `text` on the clipboard and tell how that went in the status bar
+    fn copy(&mut self, text: &str, what: &str) {
+        let status = match clipboard::copy(text) {
+            Ok(()) => format!("copied {}", what),
+            Err(e) => format!("copy failed: {}", e),
+        };
+        self.flash = Some((status, Instant::now()));
+    }
+
+    /// copy a code block of message `i`, the next one if its previous
+    /// block was the last thing copied
+    fn copy_code_block(&mut self, i: usize) {
+        let content = self.session.conversation[i]
+            .content
+            .as_deref()
+            .unwrap_or_default();
+        let blocks = markdown::code_blocks(content);
+        if blocks.is_empty() {
+            self.flash = Some(("no code block in this message".to_owned(), Instant::now()));
+            return;
+        }
+        let n = match self.copied_block {
+            Some((msg, n)) if msg == i => (n + 1) % blocks.len(),
+            _ => 0,
+        };
+        self.copied_block = Some((i, n));
+        self.copy(
+            &blocks[n],
+            &format!("code block {}/{}", n + 1, blocks.len()),
+        );
+    }
+
+    fn copy_last_code_block(&mut self) {
+        let last = self
+            .session
+            .conversation
+            .iter()
+            .enumerate()
+            .rev()
+            .filter(|(_, msg)| msg.is_assistant())
+            .find_map(|(i, msg)| {
+                let mut blocks = markdown::code_blocks(msg.content.as_deref().unwrap_or_default());
+                let n = blocks.len().checked_sub(1)?;
+                Some((i, n, blocks.pop()?))
+            });
+        match last {
+            Some((i, n, block)) => {
+                self.copied_block = Some((i, n));
+                self.copy(&block, "last code block");
+            }
+            None => self.flash = Some(("no code block to copy".to_owned(), Instant::now())),
+        }
+    }
+
+    /// plain Home/End move the cursor while there is input
+    fn home_end_scrolls(&self, modifiers: KeyModifiers) -> bool {
+        modifiers.contains(KeyModifiers::CONTROL)
+            || self.focus == Focus::Chat
+            || self.input.is_empty()
+    }
+
+    fn process_chat_key(&mut self, kev: KeyEvent) {
+        match kev.code {
+            KeyCode::Char('j') | KeyCode::Down => self.select_by(1),
+            KeyCode::Char('k') | KeyCode::Up => self.select_by(-1),
+            KeyCode::Char('g') => self.select_by(isize::MIN / 2),
+            KeyCode::Char('G') => self.select_by(isize::MAX / 2),
+            KeyCode::Char('y') => {
+                if let Some(msg) = self.selected.and_then(|i| self.session.conversation.get(i)) {
+                    let text = msg.content.clone().unwrap_or_default();
+                    self.copy(&text, "message");
+                }
+            }
+            KeyCode::Char('c') => {
+                if let Some(i) = self.selected {
+                    self.copy_code_block(i);
+                }
+            }
+            KeyCode::Char('e') => {
+                if let Some(i) = self.selected {
+                    self.edit(i);
+                }
+            }
+            KeyCode::Char('r') => self.regenerate(),
+            KeyCode::Left | KeyCode::Char('h') => self.switch_branch(-1),
+            KeyCode::Right | KeyCode::Char('l') => self.switch_branch(1),
+            KeyCode::Esc => self.selected = None,
+            KeyCode::Char('i') | KeyCode::Enter => self.focus = Focus::Input,
+            _ => {}
+        }
+    }
+
+    /// load prompt `i` into the input, sending it continues the
+    /// conversation from there on a new branch
+    fn edit(&mut self, i: usize) {
+        let Some(msg) = self.session.conversation.get(i) else {
+            return;
+        };
+        if !msg.is_user() || self.busy() {
+            return;
+        }
+        let content = msg.content.clone().unwrap_or_default();
+        self.clear();
+        self.input.insert_str(content);
+        self.editing = Some(i);
+        self.focus = Focus::Input;
+    }
+
+    /// show the previous or next alternative of the selected message
+    fn switch_branch(&mut self, delta: isize) {
+        let Some(i) = self.selected else {
+            return;
+        };
+        if !self.busy() && self.session.conversation.switch(i, delta) {
+            self.copied_block = None;
+            self.reveal_selected = true;
+            self.save_session();
+        }
+    }
+
+    /// scroll the chat by `delta` rows, following new content again once
+    /// the bottom is reached
+    fn scroll_by(&mut self, delta: i32) {
+        let mut offset = self.scroll_view_state.offset();
+        offset.y = (offset.y as i32 + delta).clamp(0, self.scroll_max as i32) as u16;
+        self.scroll_view_state.set_offset(offset);
+        self.follow = offset.y >= self.scroll_max;
+    }
+
+    fn save_session(&mut self) {
+        if let Err(e) = self.session.save() {
+            self.notification = Some(format!("failed to save session: {}", e));
+        }
+    }
+
     fn render<B: Backend>(&mut self, term: &mut Terminal<B>) -> Result<()> {
         term.draw(|frame| {
             self.render_frame(frame);
@@ -95,34 +971,389 @@ impl<'a> App<'a> {
         if self.notification.is_some() {
             self.render_notification(frame);
         } else {
-            let maxh = frame.size().height.min(8).max(1);
+            let maxh = frame.area().height.clamp(1, 8);
             let h = if self.input.lines().len() <= maxh as usize {
                 Constraint::Min(self.input.lines().len() as u16 + 2)
             } else {
                 Constraint::Length(maxh + 2)
             };
-            let [chat_area, inp] =
-                Layout::vertical([Constraint::Percentage(100), h]).areas(frame.size());
+            let mention = self.mention();
+            let bar = if self.attachments.is_empty() && mention.is_none() {
+                0
+            } else {
+                1
+            };
+            let [chat_area, bar, inp, status] = Layout::vertical([
+                Constraint::Percentage(100),
+                Constraint::Length(bar),
+                h,
+                Constraint::Length(1),
+            ])
+            .areas(frame.area());
 
+            self.render_attachments(frame, bar, mention);
             self.render_input(frame, inp);
-            frame.render_widget(self, chat_area);
+            self.render_status(frame, status);
+            frame.render_widget(&mut *self, chat_area);
+
+            if self.profile_picker.is_some() {
+                self.render_profile_picker(frame);
+            }
+            if self.session_picker.is_some() {
+                self.render_session_picker(frame);
+            }
+            if self.help {
+                self.render_help(frame);
+            }
+            if self.confirm_tool {
+                self.render_confirm_tool(frame);
+            }
+        }
+    }
+
+    fn render_status(&mut self, frame: &mut Frame<'_>, area: Rect) {
+        let profile = self.provider.active_profile();
+        let mut line = if self.provider.is_parallel() {
+            let names = self
+                .provider
+                .targets()
+                .iter()
+                .map(|i| self.provider.profiles()[*i].name.as_str())
+                .collect::<Vec<_>>();
+            Line::from(vec![
+                Span::styled(" parallel ", Style::new().black().on_magenta()),
+                Span::styled(format!(" {} ", names.join(" | ")), Style::new().dark_gray()),
+            ])
+        } else {
+            Line::from(vec![
+                Span::styled(
+                    format!(" {} ", profile.name),
+                    Style::new().black().on_cyan(),
+                ),
+                Span::styled(
+                    format!(" {} · {} ", profile.provider.as_str(), profile.model),
+                    Style::new().dark_gray(),
+                ),
+            ])
+        };
+        // parallel profiles may differ, only the overrides apply to all
+        let params = if self.provider.is_parallel() {
+            self.params.describe()
+        } else {
+            let active = self.provider.active_index();
+            self.provider.params(active, &self.params).describe()
+        };
+        if !params.is_empty() {
+            line.push_span(Span::styled(
+                format!("{} ", params.join(" ")),
+                Style::new().dark_gray(),
+            ));
         }
+        match (&self.session.persona, &self.session.system) {
+            (Some(persona), _) => line.push_span(Span::styled(
+                format!(" {} ", persona),
+                Style::new().magenta(),
+            )),
+            (None, Some(_)) => line.push_span(Span::styled(" system ", Style::new().magenta())),
+            _ => {}
+        }
+        let (prompt, completion) = self.session.tokens();
+        if prompt + completion > 0 {
+            let mut total = format!(" Σ {}+{} tok ", prompt, completion);
+            if !self.prices.is_empty() {
+                total.push_str(&format!("{} ", cost::dollars(self.session.cost())));
+            }
+            line.push_span(Span::styled(total, Style::new().dark_gray()));
+        }
+        if self.over_budget().is_some() {
+            line.push_span(Span::styled(" over budget ", Style::new().black().on_red()));
+        }
+        if let Some((ref status, at)) = self.flash {
+            if at.elapsed() < Duration::from_secs(3) {
+                line.push_span(Span::styled(format!(" {} ", status), Style::new().green()));
+            }
+        }
+        for stream in &self.streams {
+            if let Some(ref retry) = stream.retry {
+                let status = if self.streams.len() > 1 {
+                    format!(" {}: {} ", stream.profile, retry)
+                } else {
+                    format!(" {} ", retry)
+                };
+                line.push_span(Span::styled(status, Style::new().yellow()));
+            }
+        }
+
+        if self.tool_running {
+            line.push_span(Span::styled(" running tool ", Style::new().yellow()));
+        }
+
+        let hints = if self.confirm_tool {
+            Line::from("y run  a always  n skip  Esc stop ")
+        } else if self.cancel.is_some() {
+            Line::from("Esc stop  ^C quit ")
+        } else if self.focus == Focus::Chat {
+            Line::from("j/k select  ←/→ branch  e edit  r retry  y/c copy ")
+        } else if self.editing.is_some() {
+            Line::from("^J resend as new branch  Esc discard edit  ^C quit ")
+        } else {
+            Line::from("^J send  /help  Tab chat  ^P profile  ^O sessions  ^C quit ")
+        };
+        let hints = hints.dark_gray().right_aligned();
+
+        frame.render_widget(line, area);
+        frame.render_widget(hints, area);
+    }
+
+    fn render_profile_picker(&mut self, frame: &mut Frame<'_>) {
+        let items = self
+            .provider
+            .profiles()
+            .iter()
+            .enumerate()
+            .map(|(i, p)| {
+                let mark = if self.provider.in_parallel(i) {
+                    "[x]"
+                } else {
+                    "[ ]"
+                };
+                format!(
+                    "{} {} ({} · {})",
+                    mark,
+                    p.name,
+                    p.provider.as_str(),
+                    p.model
+                )
+            })
+            .collect::<Vec<_>>();
+
+        let width = items.iter().map(|s| s.chars().count()).max().unwrap_or(0) as u16 + 6;
+        let area = popup_area(frame.area(), width, items.len() as u16 + 2);
+        let list = List::new(items)
+            .block(
+                Block::default()
+                    .title_top(" profiles ")
+                    .title_bottom(" enter: use  space: toggle parallel ")
+                    .borders(Borders::ALL)
+                    .border_type(BorderType::Rounded)
+                    .border_style(Style::default().fg(Color::Cyan)),
+            )
+            .highlight_symbol("> ")
+            .highlight_spacing(HighlightSpacing::Always)
+            .highlight_style(Style::new().fg(Color::Cyan).add_modifier(Modifier::BOLD));
+
+        frame.render_widget(Clear, area);
+        if let Some(state) = self.profile_picker.as_mut() {
+            frame.render_stateful_widget(list, area, state);
+        }
+    }
+
+    fn render_session_picker(&mut self, frame: &mut Frame<'_>) {
+        let Some(picker) = self.session_picker.as_mut() else {
+            return;
+        };
+
+        let items = picker
+            .sessions
+            .iter()
+            .map(|s| {
+                Line::from(vec![
+                    Span::raw(s.title()),
+                    Span::styled(
+                        format!(
+                            "  {} · {} msgs",
+                            s.updated.format("%Y-%m-%d %H:%M"),
+                            s.conversation.len()
+                        ),
+                        Style::new().dark_gray(),
+                    ),
+                ])
+            })
+            .collect::<Vec<_>>();
+
+        let title = match (&picker.rename, picker.confirm_delete) {
+            (Some(name), _) => format!(" rename: {}_ ", name),
+            (None, true) => " delete session? y/n ".to_owned(),
+            _ => " sessions ".to_owned(),
+        };
+        let frame_area = frame.area();
+        let area = popup_area(
+            frame_area,
+            frame_area.width * 4 / 5,
+            (items.len() as u16 + 2).max(5),
+        );
+        let list = List::new(items)
+            .block(
+                Block::default()
+                    .title_top(title)
+                    .title_bottom(" enter: open  n: new  r: rename  d: delete ")
+                    .borders(Borders::ALL)
+                    .border_type(BorderType::Rounded)
+                    .border_style(Style::default().fg(Color::Cyan)),
+            )
+            .highlight_symbol("> ")
+            .highlight_spacing(HighlightSpacing::Always)
+            .highlight_style(Style::new().fg(Color::Cyan).add_modifier(Modifier::BOLD));
+
+        frame.render_widget(Clear, area);
+        frame.render_stateful_widget(list, area, &mut picker.state);
+    }
+
+    fn render_help(&mut self, frame: &mut Frame<'_>) {
+        let commands = Self::commands();
+        let usage = commands
+            .iter()
+            .map(|c| format!("/{} {}", c.name, c.args))
+            .collect::<Vec<_>>();
+        let pad = usage.iter().map(|u| u.chars().count()).max().unwrap_or(0);
+        let lines = commands
+            .iter()
+            .zip(usage)
+            .map(|(c, usage)| {
+                Line::from(vec![
+                    Span::styled(format!(" {:<pad$}  ", usage), Style::new().cyan()),
+                    Span::raw(c.help),
+                ])
+            })
+            .collect::<Vec<_>>();
+
+        let width = lines.iter().map(|l| l.width()).max().unwrap_or(0) as u16 + 3;
+        let area = popup_area(frame.area(), width, lines.len() as u16 + 2);
+        let help = Paragraph::new(lines).block(
+            Block::default()
+                .title_top(" commands ")
+                .title_bottom(" tab completes, // sends a leading slash ")
+                .borders(Borders::ALL)
+                .border_type(BorderType::Rounded)
+                .border_style(Style::default().fg(Color::Cyan)),
+        );
+        frame.render_widget(Clear, area);
+        frame.render_widget(help, area);
+    }
+
+    /// the tool call waiting for confirmation, with its arguments
+    fn render_confirm_tool(&mut self, frame: &mut Frame<'_>) {
+        let Some(call) = self.tool_queue.first() else {
+            return;
+        };
+        let name = call.function.name.as_deref().unwrap_or_default();
+        // pretty printed, a command line comes out as is
+        let args = serde_json::from_str::<serde_json::Value>(&call.function.arguments)
+            .ok()
+            .and_then(|v| serde_json::to_string_pretty(&v).ok())
+            .unwrap_or_else(|| call.function.arguments.clone());
+        let mut lines = vec![Line::from(vec![
+            Span::raw(" run "),
+            Span::styled(name, Style::new().yellow().bold()),
+            Span::raw(" with"),
+        ])];
+        lines.extend(args.lines().map(|l| Line::from(format!(" {}", l))));
+
+        let width = lines.iter().map(|l| l.width()).max().unwrap_or(0) as u16 + 3;
+        let area = popup_area(frame.area(), width.max(40), lines.len() as u16 + 2);
+        let popup = Paragraph::new(lines).wrap(Wrap { trim: false }).block(
+            Block::default()
+                .title_top(" tool call ")
+                .title_bottom(" y run · a always · n skip · Esc stop ")
+                .borders(Borders::ALL)
+                .border_type(BorderType::Rounded)
+                .border_style(Style::default().fg(Color::Yellow)),
+        );
+        frame.render_widget(Clear, area);
+        frame.render_widget(popup, area);
+    }
+
+    /// every message with its area in a scroll view `width` wide. finished
+    /// messages come first, so their index is the one in the session.
+    fn layout(&mut self, width: u16) -> Vec<(Message, Rect)> {
+        let mut layout = Vec::new();
+        let mut y = 0;
+        // the system prompt is drawn on top but listed last
+        let system = self.session.system.clone().map(|system| {
+            let msg = Message::system(system);
+            let area = row_areas(&[msg.clone()], Rect::new(0, 0, width, 0), &mut self.lines)[0];
+            y = area.height;
+            (msg, area)
+        });
+        for row in self.rows() {
+            let areas = row_areas(&row, Rect::new(0, y, width, 0), &mut self.lines);
+            y += areas.iter().map(|r| r.height).max().unwrap_or(0);
+            layout.extend(row.into_iter().zip(areas));
+        }
+        layout.extend(system);
+        layout
     }
 
-    fn calculate_message_size(&self, width: u16) -> Size {
-        let mut size = Size::default();
-        let max_width = width * 4 / 5;
-        for msg in &self.messages {
-            size.height += msg.len_by_columns(max_width) as u16 + 2;
+    /// group messages into rows, consecutive answers (or errors in their
+    /// place) are from different profiles for the same prompt and go side by side
+    fn rows(&self) -> Vec<Vec<Message>> {
+        let mut rows: Vec<Vec<Message>> = Vec::new();
+        let parallel = self.streams.len() > 1;
+        let streaming = self.streams.iter().map(|s| {
+            let mut msg = Message::assistant(s.content.clone());
+            msg.tool_calls = (!s.tool_calls.is_empty()).then(|| s.tool_calls.clone());
+            if parallel {
+                msg.provider = Some(s.profile.clone());
+            }
+            msg
+        });
+
+        // while answering again, the answers being replaced are hidden
+        let shown = match self.answer_at {
+            Some(at) if !self.streams.is_empty() => at,
+            _ => usize::MAX,
+        };
+        for msg in self.session.conversation.messages().into_iter().take(shown) {
+            match rows.last_mut() {
+                Some(row) if msg.is_answer() && row[0].is_answer() => row.push(msg),
+                _ => rows.push(vec![msg]),
+            }
         }
-        if let Some(ref delta) = self.current_message {
-            let delta_msg = Message::assistant(delta.clone());
-            size.height += delta_msg.len_by_columns(max_width) as u16 + 2;
+        if !self.streams.is_empty() {
+            rows.push(streaming.collect());
         }
 
-        size.width = width - 2;
+        rows
+    }
+
+    /// history of the first `len` messages sent to `profile`, after the
+    /// system prompt: of parallel answers only its own one (or the first one
+    /// if it did not take part) is kept. backends without tools get neither
+    /// the calls nor their results.
+    fn history_for(&self, profile: &Profile, len: usize) -> Vec<Message> {
+        let tools = profile.provider.supports_tools();
+        let mut history: Vec<Message> = Vec::new();
+        history.extend(self.session.system.clone().map(Message::system));
+        let messages = self.session.conversation.iter().take(len);
+        // an answer stopped before its first token has nothing to send,
+        // some backends reject empty text
+        let empty = |msg: &Message| {
+            msg.is_assistant()
+                && !msg.has_tool_calls()
+                && msg.content.as_deref().unwrap_or("").is_empty()
+        };
+        for msg in messages.filter(|msg| !msg.is_error() && !empty(msg)) {
+            let msg = if tools {
+                msg.clone()
+            } else if msg.is_tool() || msg.has_tool_calls() && msg.content.as_deref() == Some("") {
+                continue;
+            } else {
+                Message {
+                    tool_calls: None,
+                    ..msg.clone()
+                }
+            };
+            match history.last_mut() {
+                Some(last) if msg.is_assistant() && last.is_assistant() => {
+                    if msg.provider.as_deref() == Some(profile.name.as_str()) {
+                        *last = msg;
+                    }
+                }
+                _ => history.push(msg),
+            }
+        }
 
-        size
+        history
     }
 
     fn render_notification(&mut self, frame: &mut Frame<'_>) {
@@ -131,11 +1362,11 @@ impl<'a> App<'a> {
             let lines: Vec<_> = notif
                 .chars()
                 .collect::<Vec<char>>()
-                .chunks(frame.size().width as usize - 2)
+                .chunks(frame.area().width as usize - 2)
                 .map(|chunk| Line::from(chunk.iter().collect::<String>()))
                 .collect();
 
-            let size = frame.size();
+            let size = frame.area();
             let block = Block::default()
                 .borders(Borders::ALL)
                 .border_type(BorderType::Rounded)
@@ -149,13 +1380,51 @@ impl<'a> App<'a> {
         }
     }
 
+    /// chips of the attached files, or the paths matching the `@` mention
+    /// being typed
+    fn render_attachments(&mut self, frame: &mut Frame<'_>, area: Rect, mention: Option<String>) {
+        let mut line = Line::default();
+        match mention {
+            Some(partial) => {
+                let candidates = self.mention_completions(&partial);
+                if candidates.is_empty() {
+                    line.push_span(Span::styled(" no such path ", Style::new().dark_gray()));
+                }
+                for candidate in candidates {
+                    line.push_span(Span::styled(
+                        format!(" {} ", candidate),
+                        Style::new().cyan(),
+                    ));
+                }
+            }
+            None => {
+                // the next mention reads the directory afresh
+                self.mention_matches = None;
+                for file in &self.attachments {
+                    line.push_span(Span::styled(
+                        format!(" {} ", file.label()),
+                        Style::new().black().on_blue(),
+                    ));
+                    line.push_span(Span::raw(" "));
+                }
+            }
+        }
+        frame.render_widget(line, area);
+    }
+
     fn render_input(&mut self, frame: &mut Frame<'_>, inp: Rect) {
         let block = Block::default()
             .borders(Borders::ALL)
             .border_type(BorderType::Rounded)
-            .border_style(Style::default().fg(Color::Cyan));
+            .border_style(self.border_style(Focus::Input));
         self.input.set_block(block);
-        frame.render_widget(self.input.widget(), inp)
+        // only the focused pane shows a cursor
+        self.input.set_cursor_style(if self.focus == Focus::Input {
+            Style::new().reversed()
+        } else {
+            Style::new()
+        });
+        frame.render_widget(&self.input, inp)
     }
 
     async fn process_event(&mut self, ev: CrosstermEvent) {
@@ -168,103 +1437,558 @@ impl<'a> App<'a> {
                     ..
                 },
             ) => {
+                if self.notification.is_some() {
+                    if code == KeyCode::Esc {
+                        self.notification = None;
+                    }
+                    return;
+                }
+                if self.profile_picker.is_some() {
+                    self.process_profile_picker_key(code);
+                    return;
+                }
+                if self.session_picker.is_some() {
+                    self.process_session_picker_key(code);
+                    return;
+                }
+                if self.help {
+                    self.help = false;
+                    return;
+                }
+                if self.confirm_tool {
+                    self.process_confirm_tool_key(code);
+                    return;
+                }
+
                 match (code, modifiers, kind) {
                     (KeyCode::Char('c'), KeyModifiers::CONTROL, KeyEventKind::Press) => {
                         self.quit = true;
                         return;
                     }
+                    (KeyCode::Char('p'), KeyModifiers::CONTROL, _) => {
+                        self.profile_picker = Some(
+                            ListState::default().with_selected(Some(self.provider.active_index())),
+                        );
+                        return;
+                    }
+                    (KeyCode::Esc, _, _) | (KeyCode::Char('x'), KeyModifiers::CONTROL, _)
+                        if self.cancel.is_some() =>
+                    {
+                        if let Some(cancel) = self.cancel.as_ref() {
+                            cancel.cancel();
+                        }
+                        return;
+                    }
+                    (KeyCode::Char('o'), KeyModifiers::CONTROL, _) => {
+                        self.open_session_picker();
+                        return;
+                    }
+                    (KeyCode::PageUp, _, _) => {
+                        self.scroll_by(-(self.chat_height.saturating_sub(1).max(1) as i32));
+                        return;
+                    }
+                    (KeyCode::PageDown, _, _) => {
+                        self.scroll_by(self.chat_height.saturating_sub(1).max(1) as i32);
+                        return;
+                    }
+                    (KeyCode::Home, m, _) if self.home_end_scrolls(m) => {
+                        self.scroll_by(-(self.scroll_max as i32));
+                        return;
+                    }
+                    (KeyCode::End, m, _) if self.home_end_scrolls(m) => {
+                        self.scroll_by(self.scroll_max as i32);
+                        return;
+                    }
                     (KeyCode::Char('j'), KeyModifiers::CONTROL, _) => {
                         let prompt = self.input.lines().join("\n");
                         self.process_prompt(&prompt).await;
                         return;
                     }
-                    _ => {
-                        self.input.input(kev);
+                    (KeyCode::Char('r'), KeyModifiers::CONTROL, _) => {
+                        self.regenerate();
+                        return;
+                    }
+                    (KeyCode::Char('y'), KeyModifiers::CONTROL, _) => {
+                        let last = self
+                            .session
+                            .conversation
+                            .iter()
+                            .rev()
+                            .find(|m| m.is_assistant());
+                        if let Some(text) = last.and_then(|m| m.content.clone()) {
+                            self.copy(&text, "last answer");
+                        }
+                        return;
+                    }
+                    (KeyCode::Char('y'), KeyModifiers::ALT, _) => {
+                        self.copy_last_code_block();
+                        return;
+                    }
+                    (KeyCode::Tab, _, _)
+                        if self.focus == Focus::Input && self.mention().is_some() =>
+                    {
+                        self.complete_mention();
+                        return;
+                    }
+                    (KeyCode::Tab, _, _)
+                        if self.focus == Focus::Input
+                            && command::parse(&self.input.lines().join("\n")).is_some() =>
+                    {
+                        self.complete_command();
+                        return;
+                    }
+                    (KeyCode::Tab, _, _) => {
+                        self.focus = self.focus.next();
+                        return;
+                    }
+                    (KeyCode::BackTab, _, _) => {
+                        self.focus = self.focus.prev();
+                        return;
+                    }
+                    (KeyCode::Esc, _, _) if self.editing.is_some() => {
+                        self.editing = None;
+                        self.clear();
+                        return;
                     }
+                    _ => match self.focus {
+                        Focus::Input => {
+                            self.input.input(kev);
+                        }
+                        Focus::Chat => self.process_chat_key(kev),
+                    },
                 }
 
                 self.last_key = Some(kev);
             }
-            _ => {
-                println!("other event: {:?}", ev);
+            CrosstermEvent::Mouse(mev) => match mev.kind {
+                MouseEventKind::ScrollUp => self.scroll_by(-3),
+                MouseEventKind::ScrollDown => self.scroll_by(3),
+                _ => {}
+            },
+            _ => {}
+        }
+    }
+
+    fn process_confirm_tool_key(&mut self, code: KeyCode) {
+        match code {
+            KeyCode::Char('y') | KeyCode::Enter => self.run_tool(),
+            KeyCode::Char('a') => {
+                if let Some(name) = self
+                    .tool_queue
+                    .first()
+                    .and_then(|c| c.function.name.clone())
+                {
+                    self.allowed_tools.insert(name);
+                }
+                self.run_tool();
+            }
+            KeyCode::Char('n') => self.decline_tool(),
+            KeyCode::Esc => self.decline_tools("the user stopped the tool calls"),
+            _ => {}
+        }
+    }
+
+    fn process_profile_picker_key(&mut self, code: KeyCode) {
+        let Some(state) = self.profile_picker.as_mut() else {
+            return;
+        };
+
+        let count = self.provider.profiles().len();
+        match code {
+            KeyCode::Up | KeyCode::Char('k') => {
+                let i = state.selected().unwrap_or(0);
+                state.select(Some((i + count - 1) % count));
+            }
+            KeyCode::Down | KeyCode::Char('j') => {
+                let i = state.selected().unwrap_or(0);
+                state.select(Some((i + 1) % count));
+            }
+            KeyCode::Enter => {
+                if let Some(i) = state.selected() {
+                    self.provider.switch(i);
+                }
+                self.profile_picker = None;
+            }
+            KeyCode::Char(' ') => {
+                if let Some(i) = state.selected() {
+                    self.provider.toggle_parallel(i);
+                }
+            }
+            KeyCode::Esc => {
+                self.profile_picker = None;
+            }
+            _ => {}
+        }
+    }
+
+    fn open_session_picker(&mut self) {
+        self.save_session();
+        match Session::list() {
+            Ok(sessions) => {
+                let selected = sessions.iter().position(|s| s.id == self.session.id);
+                self.session_picker = Some(SessionPicker {
+                    sessions,
+                    state: ListState::default().with_selected(selected.or(Some(0))),
+                    rename: None,
+                    confirm_delete: false,
+                });
+            }
+            Err(e) => self.notification = Some(format!("failed to list sessions: {}", e)),
+        }
+    }
+
+    fn process_session_picker_key(&mut self, code: KeyCode) {
+        let busy = self.busy();
+        let Some(picker) = self.session_picker.as_mut() else {
+            return;
+        };
+        let selected = picker
+            .state
+            .selected()
+            .filter(|i| *i < picker.sessions.len());
+
+        if let Some(ref mut name) = picker.rename {
+            match code {
+                KeyCode::Char(c) => name.push(c),
+                KeyCode::Backspace => {
+                    name.pop();
+                }
+                KeyCode::Esc => picker.rename = None,
+                KeyCode::Enter => {
+                    let name = picker.rename.take().unwrap_or_default();
+                    if let Some(i) = selected {
+                        let session = &mut picker.sessions[i];
+                        session.name = name.trim().to_owned();
+                        if let Err(e) = session.save() {
+                            self.notification = Some(format!("failed to rename session: {}", e));
+                        }
+                        if session.id == self.session.id {
+                            self.session.name = session.name.clone();
+                        }
+                    }
+                }
+                _ => {}
+            }
+            return;
+        }
+
+        if picker.confirm_delete {
+            picker.confirm_delete = false;
+            if let (KeyCode::Char('y'), Some(i)) = (code, selected) {
+                // answers still coming in would land in the session
+                // replacing it
+                if busy && picker.sessions[i].id == self.session.id {
+                    self.flash = Some((
+                        "not deleted, the session is still busy".to_owned(),
+                        Instant::now(),
+                    ));
+                    return;
+                }
+                let session = picker.sessions.remove(i);
+                if let Err(e) = Session::delete(&session.id) {
+                    self.notification = Some(format!("failed to delete session: {}", e));
+                }
+                if session.id == self.session.id {
+                    self.session = self.new_session();
+                }
+            }
+            return;
+        }
+
+        let count = picker.sessions.len().max(1);
+        match code {
+            KeyCode::Up | KeyCode::Char('k') => {
+                let i = picker.state.selected().unwrap_or(0);
+                picker.state.select(Some((i + count - 1) % count));
+            }
+            KeyCode::Down | KeyCode::Char('j') => {
+                let i = picker.state.selected().unwrap_or(0);
+                picker.state.select(Some((i + 1) % count));
+            }
+            KeyCode::Char('r') if selected.is_some() => {
+                picker.rename = selected.map(|i| picker.sessions[i].title());
+            }
+            KeyCode::Char('d') if selected.is_some() => {
+                picker.confirm_delete = true;
+            }
+            KeyCode::Char('n') if !busy => {
+                self.session_picker = None;
+                self.open_session(self.new_session());
             }
+            KeyCode::Enter if !busy => {
+                if let Some(i) = selected {
+                    let session = picker.sessions.swap_remove(i);
+                    self.session_picker = None;
+                    self.open_session(session);
+                }
+            }
+            KeyCode::Esc => {
+                self.session_picker = None;
+            }
+            _ => {}
         }
     }
 
     async fn process_prompt<S: AsRef<str>>(&mut self, prompt: S) {
         let prompt = prompt.as_ref();
-        if prompt.is_empty() {
+        if prompt.is_empty() || self.busy() {
             return;
         }
+        if self.process_command(prompt) {
+            return;
+        }
+        // `//` escapes a prompt starting with a slash
+        let prompt = prompt.strip_prefix('/').unwrap_or(prompt);
+        if !self.check_budget() {
+            return;
+        }
+        // mentions of paths that aren't files are left alone, `@someone`
+        // is no file and `@src/` no text
+        let mut files = self.attachments.clone();
+        for path in attach::mentions(prompt) {
+            if !std::path::Path::new(path).is_file() {
+                continue;
+            }
+            match Attachment::read(path) {
+                Ok(file) => files.push(file),
+                Err(e) => {
+                    self.flash = Some((format!("not sent, {}", e), Instant::now()));
+                    return;
+                }
+            }
+        }
+        let prompt = attach::inline(prompt, &files);
+        self.attachments.clear();
 
-        let prompt = prompt.to_string();
-        self.messages.push(Message::user(prompt.clone()));
-        let history = self.messages.clone();
-
-        let llm = Arc::clone(&self.llm);
-        let tx = self.event_manager.get_sender();
-        tokio::spawn(async move {
-            let mut llm = llm.lock().await;
-            llm.request(&prompt, history, tx)
-                .await
-                .expect("llm request failed");
-        });
+        // an edited prompt replaces the original one on a new branch
+        let at = self
+            .editing
+            .take()
+            .unwrap_or(self.session.conversation.len());
+        self.session.conversation.insert(at, Message::user(prompt));
+        self.tool_rounds = 0;
+        self.start_streams(at + 1);
+        self.answer_at = Some(at + 1);
+        self.selected = None;
+        self.follow = true;
         self.clear();
     }
 
+    /// ask for the answer to the last prompt again, the current one is kept
+    /// as an alternative
+    fn regenerate(&mut self) {
+        if self.busy() {
+            return;
+        }
+        let conversation = &self.session.conversation;
+        let Some(at) = conversation.iter().rposition(|msg| msg.is_user()) else {
+            return;
+        };
+        if !self.check_budget() {
+            return;
+        }
+        self.tool_rounds = 0;
+        self.start_streams(at + 1);
+        self.answer_at = Some(at + 1);
+        self.selected = None;
+        self.follow = true;
+    }
+
+    /// ask every target profile to answer the first `len` messages. tools
+    /// are offered to a single one only, parallel tool calls would mix.
+    fn start_streams(&mut self, len: usize) {
+        let cancel = CancellationToken::new();
+        let targets = self.provider.targets();
+        for &index in &targets {
+            let profile = &self.provider.profiles()[index];
+            let tools = if targets.len() == 1 && profile.provider.supports_tools() {
+                self.tools.specs()
+            } else {
+                Vec::new()
+            };
+            let history = self.history_for(profile, len);
+            let profile = profile.name.clone();
+            let params = self.provider.params(index, &self.params);
+            let id = self.next_stream_id;
+            self.next_stream_id += 1;
+            self.streams.push(Stream {
+                id,
+                profile,
+                content: String::new(),
+                done: false,
+                interrupted: false,
+                error: None,
+                retry: None,
+                stats: None,
+                tool_calls: Vec::new(),
+            });
+
+            let llm = self.provider.service(index);
+            let tx = self.event_manager.get_sender();
+            let cancel = cancel.clone();
+            // a panicking request still has to end its stream, or the
+            // answer would stay pending for good
+            let request = tokio::spawn({
+                let tx = tx.clone();
+                async move {
+                    let mut llm = llm.lock().await;
+                    llm.request(id, history, params, tools, tx, cancel).await
+                }
+            });
+            tokio::spawn(async move {
+                let res = request.await.unwrap_or_else(|e| Err(e.into()));
+                if let Err(e) = res {
+                    tx.send(Event::LLMEventError(id, e)).unwrap();
+                }
+            });
+        }
+        self.cancel = Some(cancel);
+    }
+
     fn clear(&mut self) {
         self.input.select_all();
         self.input.cut();
     }
 }
 
+impl<'a> Default for App<'a> {
+    fn default() -> Self {
+        Self::new(Config::from_env())
+    }
+}
+
+/// a `width` x `height` rect centered in `area`
+fn popup_area(area: Rect, width: u16, height: u16) -> Rect {
+    let [area] = Layout::horizontal([Constraint::Length(width.min(area.width))])
+        .flex(layout::Flex::Center)
+        .areas(area);
+    let [area] = Layout::vertical([Constraint::Length(height.min(area.height))])
+        .flex(layout::Flex::Center)
+        .areas(area);
+    area
+}
+
 impl<'a> Widget for &mut App<'a> {
     fn render(self, area: Rect, buf: &mut Buffer) {
-        let scroll_size = self.calculate_message_size(area.width);
+        let block = Block::default()
+            .borders(Borders::ALL)
+            .border_type(BorderType::Rounded)
+            .border_style(self.border_style(Focus::Chat))
+            .title(format!(" {} ", self.session.title()));
+        let area = {
+            let inner = block.inner(area);
+            block.render(area, buf);
+            inner
+        };
+
+        self.lines.frame();
+        let layout = self.layout(area.width.saturating_sub(2));
+        let scroll_size = Size::new(
+            area.width.saturating_sub(2),
+            layout.iter().map(|(_, r)| r.bottom()).max().unwrap_or(0),
+        );
+
+        self.chat_height = area.height;
+        self.scroll_max = scroll_size.height.saturating_sub(area.height);
 
         let mut offset = self.scroll_view_state.offset();
-        if scroll_size.height > area.height {
-            offset.y = scroll_size.height - area.height;
+        offset.y = if self.follow {
+            self.scroll_max
+        } else {
+            offset.y.min(self.scroll_max)
+        };
+        if self.reveal_selected {
+            // bring the selected message into view, its top if it is too tall
+            if let Some((_, r)) = self.selected.and_then(|i| layout.get(i)) {
+                if r.bottom() > offset.y + area.height {
+                    offset.y = r.bottom() - area.height;
+                }
+                if r.y < offset.y || r.height > area.height {
+                    offset.y = r.y;
+                }
+                self.follow = offset.y >= self.scroll_max;
+            }
+            self.reveal_selected = false;
         }
         self.scroll_view_state.set_offset(offset);
 
         let mut scroll_view = ScrollView::new(scroll_size);
-        self.render_into_scroll_view(scroll_view.buf_mut());
+        for (i, (msg, r)) in layout.iter().enumerate() {
+            let lines = self.lines.get(msg, r.width.saturating_sub(2)).to_vec();
+            msg.render_lines(lines, *r, scroll_view.buf_mut());
+            if self.selected == Some(i) {
+                highlight_border(scroll_view.buf_mut(), *r);
+            }
+        }
         scroll_view.render(area, buf, &mut self.scroll_view_state);
-    }
-}
 
-impl<'a> App<'a> {
-    fn render_into_scroll_view(&mut self, buf: &mut Buffer) {
-        let area = buf.area;
-        let mut offset = area.y;
-        let max_width = area.width * 4 / 5;
-        self.messages.iter().for_each(|msg| {
-            let x = if msg.role.as_ref().unwrap() == "user" {
-                area.x + area.width - max_width
-            } else {
-                area.x
+        if !self.follow && !self.streams.is_empty() && offset.y < self.scroll_max {
+            let indicator = Span::styled(
+                " ↓ new content below, End to follow ",
+                Style::new().black().on_yellow(),
+            );
+            let width = (indicator.width() as u16).min(area.width);
+            let bottom = Rect {
+                x: area.x + (area.width - width) / 2,
+                y: area.bottom().saturating_sub(1),
+                width,
+                height: 1,
             };
+            indicator.render(bottom, buf);
+        }
+    }
+}
 
-            let sub_area = Rect {
-                x,
-                y: offset,
-                width: max_width,
-                height: msg.len_by_columns(max_width) as u16 + 2,
-            };
-            offset += sub_area.height;
-            msg.render(sub_area, buf)
-        });
+/// recolor the border of the message at `area`, keeping its title
+fn highlight_border(buf: &mut Buffer, area: Rect) {
+    let style = Style::new().yellow().add_modifier(Modifier::BOLD);
+    let area = area.intersection(buf.area);
+    if area.is_empty() {
+        return;
+    }
+    for x in area.left()..area.right() {
+        buf[(x, area.top())].set_style(style);
+        buf[(x, area.bottom() - 1)].set_style(style);
+    }
+    for y in area.top()..area.bottom() {
+        buf[(area.left(), y)].set_style(style);
+        buf[(area.right() - 1, y)].set_style(style);
+    }
+}
 
-        if let Some(ref delta) = self.current_message {
-            let delta_msg = Message::assistant(delta.clone());
-            let sub_area = Rect {
-                x: area.x,
-                y: offset,
-                width: max_width,
-                height: delta_msg.len_by_columns(max_width) as u16 + 2,
-            };
-            delta_msg.render(sub_area, buf);
+/// where the messages of a row go: a single message takes 4/5 of the width,
+/// user messages right aligned, the system prompt all of it; parallel answers split the width evenly and
+/// share the height of the tallest one.
+fn row_areas(row: &[Message], area: Rect, lines: &mut LineCache) -> Vec<Rect> {
+    let mut height =
+        |msg: &Message, width: u16| lines.get(msg, width.saturating_sub(2)).len() as u16 + 2;
+    if let [msg] = row {
+        if msg.is_system() {
+            let height = height(msg, area.width);
+            return vec![Rect { height, ..area }];
         }
+        let max_width = area.width * 4 / 5;
+        let x = if msg.is_answer() {
+            area.x
+        } else {
+            area.x + area.width - max_width
+        };
+        return vec![Rect {
+            x,
+            y: area.y,
+            width: max_width,
+            height: height(msg, max_width),
+        }];
     }
+
+    let width = area.width / row.len().max(1) as u16;
+    let height = row.iter().map(|msg| height(msg, width)).max().unwrap_or(0);
+    (0..row.len() as u16)
+        .map(|i| Rect {
+            x: area.x + i * width,
+            y: area.y,
+            width,
+            height,
+        })
+        .collect()
 }
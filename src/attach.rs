@@ -0,0 +1,163 @@
+use std::{fs, io::Read, path::Path};
+
+/// files larger than this are refused, they would not fit a context anyway
+pub const MAX_FILE: u64 = 256 * 1024;
+/// files one `/attach` may add, a stray `**` should not send a whole tree
+pub const MAX_FILES: usize = 32;
+
+/// a local file sent along with the next prompt
+#[derive(Debug, Clone, PartialEq)]
+pub struct Attachment {
+    pub path: String,
+    pub content: String,
+}
+
+impl Attachment {
+    /// read a text file, binary and oversized ones are refused
+    pub fn read(path: &str) -> Result<Self, String> {
+        let fail = |e: String| format!("{}: {}", path, e);
+        let meta = fs::metadata(path).map_err(|e| fail(e.to_string()))?;
+        if meta.is_dir() {
+            return Err(fail("is a directory".to_owned()));
+        }
+        if meta.len() > MAX_FILE {
+            return Err(fail(format!("larger than {}", size(MAX_FILE))));
+        }
+        let mut bytes = Vec::new();
+        fs::File::open(path)
+            .and_then(|mut f| f.read_to_end(&mut bytes))
+            .map_err(|e| fail(e.to_string()))?;
+        // the same test as git: a NUL byte early on
+        if bytes.iter().take(8000).any(|&b| b == 0) {
+            return Err(fail("binary file".to_owned()));
+        }
+        let content = String::from_utf8(bytes).map_err(|_| fail("not utf-8 text".to_owned()))?;
+        Ok(Self {
+            path: path.to_owned(),
+            content,
+        })
+    }
+
+    /// label of the chip above the input, e.g. "src/app.rs 12.3K"
+    pub fn label(&self) -> String {
+        format!("{} {}", self.path, size(self.content.len() as u64))
+    }
+
+    /// the file name followed by its content in a fenced block tagged
+    /// with the extension, the fence outlasting any backticks inside
+    pub fn block(&self) -> String {
+        let mut run = 0;
+        let mut longest = 0;
+        for c in self.content.chars() {
+            run = if c == '`' { run + 1 } else { 0 };
+            longest = longest.max(run);
+        }
+        let fence = "`".repeat(longest.max(2) + 1);
+        let lang = Path::new(&self.path)
+            .extension()
+            .map(|e| e.to_string_lossy())
+            .unwrap_or_default();
+        let newline = if self.content.ends_with('\n') {
+            ""
+        } else {
+            "\n"
+        };
+        format!(
+            "{}:\n{}{}\n{}{}{}",
+            self.path, fence, lang, self.content, newline, fence
+        )
+    }
+}
+
+fn size(bytes: u64) -> String {
+    if bytes < 1024 {
+        format!("{}B", bytes)
+    } else {
+        format!("{:.1}K", bytes as f64 / 1024.0)
+    }
+}
+
+/// the files matching `pattern` (and the reasons some of them were
+/// skipped), directories are left out
+pub fn glob(pattern: &str) -> Result<(Vec<Attachment>, Vec<String>), String> {
+    let paths = glob::glob(pattern).map_err(|e| format!("bad pattern: {}", e))?;
+    let mut files = paths
+        .filter_map(|p| p.ok())
+        .filter(|p| !p.is_dir())
+        .map(|p| p.to_string_lossy().into_owned())
+        .collect::<Vec<_>>();
+    if files.is_empty() {
+        return Err(format!("no files match {}", pattern));
+    }
+    if files.len() > MAX_FILES {
+        return Err(format!(
+            "{} files match {}, at most {} can be attached",
+            files.len(),
+            pattern,
+            MAX_FILES
+        ));
+    }
+    files.sort();
+
+    let mut attached = Vec::new();
+    let mut skipped = Vec::new();
+    for file in files {
+        match Attachment::read(&file) {
+            Ok(attachment) => attached.push(attachment),
+            Err(e) => skipped.push(e),
+        }
+    }
+    Ok((attached, skipped))
+}
+
+/// paths mentioned as `@path` in `prompt`, punctuation right after them
+/// is not part of the path
+pub fn mentions(prompt: &str) -> Vec<&str> {
+    prompt
+        .split_whitespace()
+        .filter_map(|word| word.strip_prefix('@'))
+        .map(|path| path.trim_end_matches(['.', ',', ';', ':', '!', '?', ')', '\'', '"']))
+        .filter(|path| !path.is_empty())
+        .collect()
+}
+
+/// `prompt` with the files appended, each once
+pub fn inline(prompt: &str, attachments: &[Attachment]) -> String {
+    let mut text = prompt.to_owned();
+    let mut seen = Vec::new();
+    for attachment in attachments {
+        if seen.contains(&&attachment.path) {
+            continue;
+        }
+        seen.push(&attachment.path);
+        text.push_str("\n\n");
+        text.push_str(&attachment.block());
+    }
+    text
+}
+
+/// paths starting with `prefix`, directories with a trailing slash. hidden
+/// entries only show up once the prefix asks for them.
+pub fn complete(prefix: &str) -> Vec<String> {
+    let (dir, name) = match prefix.rfind('/') {
+        Some(i) => prefix.split_at(i + 1),
+        None => ("", prefix),
+    };
+    let Ok(entries) = fs::read_dir(if dir.is_empty() { "." } else { dir }) else {
+        return Vec::new();
+    };
+    let mut candidates = entries
+        .filter_map(|entry| entry.ok())
+        .filter_map(|entry| {
+            let file_name = entry.file_name().to_string_lossy().into_owned();
+            if !file_name.starts_with(name) || file_name.starts_with('.') && !name.starts_with('.')
+            {
+                return None;
+            }
+            let slash = if entry.path().is_dir() { "/" } else { "" };
+            Some(format!("{}{}{}", dir, file_name, slash))
+        })
+        .collect::<Vec<_>>();
+    candidates.sort();
+    candidates
+}
@@ -0,0 +1,69 @@
+#[cfg(test)]
+mod tests {
+    use crate::attach::{self, Attachment, MAX_FILE};
+    use std::fs;
+
+    #[test]
+    fn attach_read() {
+        let dir = std::env::temp_dir().join(format!("llmi-attach-{}", std::process::id()));
+        fs::create_dir_all(dir.join("sub")).unwrap();
+        let path = |name: &str| dir.join(name).to_string_lossy().into_owned();
+        fs::write(path("notes.md"), "# notes\n").unwrap();
+        fs::write(path("image.png"), b"\x89PNG\r\n\x1a\n\0\0\0\rIHDR").unwrap();
+        fs::write(path("big.txt"), "x".repeat(MAX_FILE as usize + 1)).unwrap();
+
+        let notes = Attachment::read(&path("notes.md")).unwrap();
+        assert_eq!(notes.content, "# notes\n");
+        assert!(notes.label().ends_with("notes.md 8B"));
+        let err = |name| Attachment::read(&path(name)).unwrap_err();
+        assert!(err("image.png").ends_with("binary file"));
+        assert!(err("big.txt").ends_with("larger than 256.0K"));
+        assert!(err("sub").ends_with("is a directory"));
+
+        // directories are left out, the files come sorted
+        let (files, skipped) = attach::glob(&path("*")).unwrap();
+        assert_eq!(files, [notes]);
+        assert_eq!(skipped.len(), 2);
+        assert!(attach::glob(&path("*.rs"))
+            .unwrap_err()
+            .starts_with("no files match"));
+
+        let prefix = path("");
+        assert_eq!(attach::complete(&path("s")), [format!("{}sub/", prefix)]);
+        assert_eq!(attach::complete(&path("b")).len(), 1);
+        assert_eq!(attach::complete(&path("")).len(), 4);
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn attach_inline() {
+        let file = Attachment {
+            path: "src/main.rs".to_owned(),
+            content: "fn main() {}".to_owned(),
+        };
+        assert_eq!(
+            attach::inline("what does @src/main.rs do?", &[file.clone(), file]),
+            "what does @src/main.rs do?\n\nsrc/main.rs:\n```rs\nfn main() {}\n```"
+        );
+
+        // the fence has to outlast the one inside
+        let readme = Attachment {
+            path: "README".to_owned(),
+            content: "run\n````sh\nmake\n````\n".to_owned(),
+        };
+        assert_eq!(
+            readme.block(),
+            "README:\n`````\nrun\n````sh\nmake\n````\n`````"
+        );
+    }
+
+    #[test]
+    fn attach_mentions() {
+        assert_eq!(
+            attach::mentions("compare @src/a.rs, @b.rs and mail@example.com (see @c.md)"),
+            ["src/a.rs", "b.rs", "c.md"]
+        );
+        assert!(attach::mentions("just an @ sign").is_empty());
+    }
+}
@@ -1,21 +1,89 @@
 use async_trait::async_trait;
-use regex::Regex;
+use futures::Stream;
 use reqwest::{header::CONTENT_TYPE, Client};
-use serde_json::json;
-use std::{collections::HashMap, env, io::Result};
-use tokio::sync::mpsc::UnboundedSender;
+use serde_json::{json, Value};
+use tokio::{select, sync::mpsc::UnboundedSender};
+use tokio_util::sync::CancellationToken;
 
-use crate::event::Event;
+use crate::config::{GenerationParams, Profile};
+use crate::error::LLMError;
+use crate::event::{Event, StreamId};
 use crate::llm::*;
+use crate::sse;
+use crate::tool::ToolSpec;
 
 #[derive(Debug)]
 pub struct ChatGPT {
     cli: Client,
+    profile: Profile,
+    /// off if the profile says so or the server answered with plain json
+    stream: bool,
 }
 
 impl ChatGPT {
-    pub fn new() -> Self {
-        Self { cli: Client::new() }
+    pub fn new(profile: Profile) -> Self {
+        Self {
+            cli: http_client(),
+            stream: profile.stream.unwrap_or(true),
+            profile,
+        }
+    }
+
+    pub(crate) fn body(
+        &self,
+        history: &[Message],
+        params: &GenerationParams,
+        tools: &[ToolSpec],
+    ) -> Value {
+        let messages = history.iter().map(message).collect::<Vec<_>>();
+
+        let mut data = json!({
+            "model": self.profile.model,
+            "stream": self.stream,
+            "messages": messages
+        });
+        if !tools.is_empty() {
+            let tools = tools
+                .iter()
+                .map(|tool| {
+                    json!({
+                        "type": "function",
+                        "function": {
+                            "name": tool.name,
+                            "description": tool.description,
+                            "parameters": tool.parameters,
+                        },
+                    })
+                })
+                .collect::<Vec<_>>();
+            data["tools"] = json!(tools);
+        }
+        if self.stream && self.profile.stream_usage.unwrap_or(true) {
+            data["stream_options"] = json!({ "include_usage": true });
+        }
+        let fields = [
+            ("max_tokens", json!(params.max_tokens)),
+            ("temperature", json!(params.temperature)),
+            ("top_p", json!(params.top_p)),
+            ("stop", json!(params.stop)),
+            ("seed", json!(params.seed)),
+            ("presence_penalty", json!(params.presence_penalty)),
+            ("frequency_penalty", json!(params.frequency_penalty)),
+        ];
+        for (name, value) in fields {
+            if !value.is_null() {
+                data[name] = value;
+            }
+        }
+        if let Some(format) = params.response_format.as_deref() {
+            let format = if format == "json" {
+                "json_object"
+            } else {
+                format
+            };
+            data["response_format"] = json!({ "type": format });
+        }
+        data
     }
 }
 
@@ -23,71 +91,175 @@ impl ChatGPT {
 impl LLMService for ChatGPT {
     async fn request(
         &mut self,
-        prompt: &str,
-        mut history: Vec<Message>,
+        id: StreamId,
+        history: Vec<Message>,
+        params: GenerationParams,
+        tools: Vec<ToolSpec>,
         tx: UnboundedSender<Event>,
-    ) -> Result<()> {
-        let endpoint = env::var("LLM_ENDPOINT").unwrap_or("".to_owned());
-        let api_key = env::var("LLM_API_KEY").unwrap_or("".to_owned());
-        let model = env::var("LLM_MODEL").unwrap_or("mixtral-8x7b-32768".to_owned());
+        cancel: CancellationToken,
+    ) -> Result<(), LLMError> {
+        let endpoint = self.profile.endpoint();
+        let api_key = self.profile.api_key();
+
+        let data = self.body(&history, &params, &tools);
+
+        tx.send(Event::LLMEventStart(id)).unwrap();
+
+        let timer = Timer::start();
+        let req = || {
+            self.cli
+                .post(&endpoint)
+                .bearer_auth(&api_key)
+                .header(CONTENT_TYPE, "application/json")
+                .json(&data)
+        };
+        let Some(resp) = send_with_retry(id, &self.profile.retry, req, &tx, &cancel).await? else {
+            tx.send(Event::LLMEventCancelled(id)).unwrap();
+            return Ok(());
+        };
+
+        // whatever was asked for, the content type tells what came back
+        let is_json = resp
+            .headers()
+            .get(CONTENT_TYPE)
+            .and_then(|v| v.to_str().ok())
+            .is_some_and(|v| v.starts_with("application/json"));
+        if !is_json {
+            return read_stream(id, resp.bytes_stream(), timer, &tx, &cancel).await;
+        }
+        // a server ignoring `stream` is not asked for it again
+        self.stream = false;
+        let body = select! {
+            _ = cancel.cancelled() => {
+                tx.send(Event::LLMEventCancelled(id)).unwrap();
+                return Ok(());
+            }
+            body = resp.text() => body?,
+        };
+        read_json(id, &body, timer, &tx)
+    }
+}
 
-        history.push(Message::user(prompt.to_owned()));
-        let messages = history
+/// `msg` in the shape of the chat completions api, answers asking for tools
+/// carry their calls and tool results the id of the call they answer
+fn message(msg: &Message) -> Value {
+    let mut value = json!({ "role": msg.role, "content": msg.content });
+    if let Some(ref calls) = msg.tool_calls {
+        let calls = calls
             .iter()
-            .map(|msg| {
-                let mut hm = HashMap::new();
-                hm.insert("role", msg.role.clone().unwrap());
-                hm.insert("content", msg.content.clone().unwrap());
-                hm
+            .map(|call| {
+                json!({
+                    "id": call.id,
+                    "type": "function",
+                    "function": { "name": call.function.name, "arguments": call.function.arguments },
+                })
             })
             .collect::<Vec<_>>();
+        value["tool_calls"] = json!(calls);
+        if msg.content.as_deref() == Some("") {
+            value["content"] = Value::Null;
+        }
+    }
+    if let Some(ref id) = msg.tool_call_id {
+        value["tool_call_id"] = json!(id);
+    }
+    value
+}
 
-        let data = json!({
-            "model": model,
-            "stream": true,
-            "max_tokens": 3000,
-            "messages": messages
-        });
+/// forward a complete, non-streamed answer as a single delta, ends with
+/// `LLMEventStats` and `LLMEventEnd` unless an error is returned
+pub(crate) fn read_json(
+    id: StreamId,
+    body: &str,
+    mut timer: Timer,
+    tx: &UnboundedSender<Event>,
+) -> Result<(), LLMError> {
+    let data = serde_json::from_str::<LLMResponse>(body).map_err(|_| api_error(body))?;
+    let Some(msg) = data.extract_message() else {
+        return Err(api_error(body));
+    };
+    if msg.content.as_deref().is_some_and(|s| !s.is_empty()) || msg.has_tool_calls() {
+        timer.token();
+    }
+    tx.send(Event::LLMEventDelta(id, msg)).unwrap();
 
-        tx.send(Event::LLMEventStart).unwrap();
-
-        let resp = self
-            .cli
-            .post(endpoint)
-            .bearer_auth(api_key)
-            .header(CONTENT_TYPE, "application/json")
-            .json(&data)
-            .send()
-            .await
-            .unwrap();
-
-        match resp.error_for_status() {
-            Err(_e) => {
-                tx.send(Event::LLMEventEnd).unwrap();
-            }
-            Ok(mut resp) => {
-                while let Some(bytes) = resp.chunk().await.unwrap() {
-                    let str = std::str::from_utf8(&bytes).unwrap();
-                    let re = Regex::new(r"data:\s(.*)").unwrap();
-
-                    for caps in re.captures_iter(str) {
-                        let (_, [payload]) = caps.extract();
-                        if payload == "[DONE]" {
-                            tx.send(Event::LLMEventEnd).unwrap();
-                        } else {
-                            match serde_json::from_str::<LLMResponse>(payload) {
-                                Ok(data) => {
-                                    assert!(data.choices.len() > 0);
-                                    tx.send(Event::LLMEventDelta(data.extract_message()))
-                                        .unwrap();
-                                }
-                                Err(_) => {}
-                            }
-                        }
-                    }
-                }
+    let mut stats = timer.stats(data.usage());
+    // it all came at once, there is no speed to measure
+    if data
+        .usage()
+        .map_or(true, |usage| usage.completion_time == 0.0)
+    {
+        stats.generation = None;
+    }
+    stats.stop_reason = data.finish_reason().map(String::from);
+    tx.send(Event::LLMEventStats(id, stats)).unwrap();
+    tx.send(Event::LLMEventEnd(id)).unwrap();
+    Ok(())
+}
+
+/// forward the deltas of an sse body, ends with `LLMEventStats` and
+/// `LLMEventEnd` or `LLMEventCancelled` unless an error is returned
+pub(crate) async fn read_stream<S, B, E>(
+    id: StreamId,
+    body: S,
+    timer: Timer,
+    tx: &UnboundedSender<Event>,
+    cancel: &CancellationToken,
+) -> Result<(), LLMError>
+where
+    S: Stream<Item = Result<B, E>>,
+    B: AsRef<[u8]>,
+    E: Into<LLMError>,
+{
+    read_events(id, sse::decode(body), timer, tx, cancel, |event, reply| {
+        if event.data == "[DONE]" {
+            return Ok(Flow::Done);
+        }
+        if event.data.is_empty() {
+            return Ok(Flow::Continue);
+        }
+
+        let value = match serde_json::from_str::<Value>(&event.data) {
+            Ok(value) if event.event != "error" && value.get("error").is_none() => value,
+            _ => return Err(api_error(&event.data)),
+        };
+        // json of another shape, e.g. a keep-alive, has nothing to show
+        let Ok(data) = serde_json::from_value::<LLMResponse>(value) else {
+            return Ok(Flow::Continue);
+        };
+        if let Some(u) = data.usage() {
+            reply.usage = Some(u.clone());
+        }
+        if let Some(reason) = data.finish_reason() {
+            reply.stop_reason = Some(reason.to_owned());
+        }
+        if let Some(msg) = data.extract_message() {
+            if msg.content.as_deref().is_some_and(|s| !s.is_empty()) || msg.has_tool_calls() {
+                reply.timer.token();
             }
+            tx.send(Event::LLMEventDelta(id, msg)).unwrap();
         }
-        Ok(())
+        Ok(Flow::Continue)
+    })
+    .await
+}
+
+/// an error sent in place of a chunk, `{"error": {"message": ..}}` usually
+fn api_error(payload: &str) -> LLMError {
+    let Ok(value) = serde_json::from_str::<Value>(payload) else {
+        return LLMError::Parse(format!("unexpected event: {}", payload));
+    };
+    match value.get("error") {
+        Some(error) => LLMError::Api(
+            error
+                .get("message")
+                .and_then(|m| m.as_str())
+                .map(String::from)
+                .unwrap_or_else(|| error.to_string()),
+        ),
+        None => match serde_json::from_str::<LLMResponse>(payload) {
+            Err(e) => e.into(),
+            Ok(_) => LLMError::Api(payload.to_owned()),
+        },
     }
 }
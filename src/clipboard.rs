@@ -0,0 +1,23 @@
+use base64::{engine::general_purpose::STANDARD, Engine};
+use std::{
+    env,
+    io::{stdout, Result, Write},
+};
+
+/// put `text` on the system clipboard with an OSC 52 escape sequence, the
+/// terminal does the copying so this works over ssh as well
+pub fn copy(text: &str) -> Result<()> {
+    let mut out = stdout();
+    out.write_all(osc52(text, env::var_os("TMUX").is_some()).as_bytes())?;
+    out.flush()
+}
+
+/// inside tmux the sequence is wrapped to be passed through to the terminal
+pub fn osc52(text: &str, tmux: bool) -> String {
+    let seq = format!("\x1b]52;c;{}\x07", STANDARD.encode(text));
+    if tmux {
+        format!("\x1bPtmux;\x1b{}\x1b\\", seq)
+    } else {
+        seq
+    }
+}
@@ -0,0 +1,25 @@
+#[cfg(test)]
+mod tests {
+    use crate::clipboard::osc52;
+    use base64::{engine::general_purpose::STANDARD, Engine};
+
+    #[test]
+    fn clipboard_osc52() {
+        assert_eq!(osc52("hello", false), "\x1b]52;c;aGVsbG8=\x07");
+
+        // the payload is the text in base64, newlines and all
+        let text = "fn main() {\n    println!(\"ü\");\n}\n";
+        let seq = osc52(text, false);
+        let payload = seq
+            .strip_prefix("\x1b]52;c;")
+            .and_then(|s| s.strip_suffix('\x07'))
+            .unwrap();
+        assert_eq!(STANDARD.decode(payload).unwrap(), text.as_bytes());
+
+        // tmux passes it through with every escape inside doubled
+        assert_eq!(
+            osc52("hello", true),
+            "\x1bPtmux;\x1b\x1b]52;c;aGVsbG8=\x07\x1b\\"
+        );
+    }
+}
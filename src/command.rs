@@ -0,0 +1,123 @@
+/// a slash command typed into the input box, `run` gets the trimmed
+/// argument and returns the status to show
+pub struct Command<T> {
+    pub name: &'static str,
+    /// argument synopsis for `/help`, e.g. "[name|none]"
+    pub args: &'static str,
+    pub help: &'static str,
+    pub run: fn(&mut T, &str) -> String,
+    /// candidates for the argument typed so far
+    pub complete: Option<fn(&T, &str) -> Vec<String>>,
+}
+
+/// the commands known to `T`, in the order `/help` lists them
+pub struct Registry<T> {
+    commands: Vec<Command<T>>,
+}
+
+/// name and argument of a command, `None` for plain text. a leading `//`
+/// escapes the slash, the prompt is sent with a single one.
+pub fn parse(input: &str) -> Option<(&str, &str)> {
+    let command = input.strip_prefix('/').filter(|s| !s.starts_with('/'))?;
+    let (name, arg) = command
+        .split_once(char::is_whitespace)
+        .unwrap_or((command, ""));
+    Some((name, arg.trim()))
+}
+
+/// longest prefix all `candidates` share
+pub fn common_prefix(candidates: &[String]) -> String {
+    let Some(first) = candidates.first() else {
+        return String::new();
+    };
+    let mut prefix = first.as_str();
+    for other in &candidates[1..] {
+        let len = prefix
+            .char_indices()
+            .zip(other.chars())
+            .take_while(|((_, a), b)| a == b)
+            .last()
+            .map_or(0, |((i, a), _)| i + a.len_utf8());
+        prefix = &prefix[..len];
+    }
+    prefix.to_owned()
+}
+
+impl<T> Registry<T> {
+    pub fn new() -> Self {
+        Self {
+            commands: Vec::new(),
+        }
+    }
+
+    pub fn command(
+        mut self,
+        name: &'static str,
+        args: &'static str,
+        help: &'static str,
+        run: fn(&mut T, &str) -> String,
+    ) -> Self {
+        self.commands.push(Command {
+            name,
+            args,
+            help,
+            run,
+            complete: None,
+        });
+        self
+    }
+
+    /// argument completion of the command added last
+    pub fn complete(mut self, complete: fn(&T, &str) -> Vec<String>) -> Self {
+        if let Some(command) = self.commands.last_mut() {
+            command.complete = Some(complete);
+        }
+        self
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = &Command<T>> {
+        self.commands.iter()
+    }
+
+    pub fn get(&self, name: &str) -> Option<&Command<T>> {
+        self.commands.iter().find(|c| c.name == name)
+    }
+
+    /// run the command in `input` on `target`, `None` if it is no command
+    pub fn dispatch(&self, target: &mut T, input: &str) -> Option<String> {
+        let (name, arg) = parse(input)?;
+        Some(match self.get(name) {
+            Some(command) => (command.run)(target, arg),
+            None => format!("unknown command /{}, /help lists them, // sends it", name),
+        })
+    }
+
+    /// every way to complete `input`, each the whole new input
+    pub fn completions(&self, target: &T, input: &str) -> Vec<String> {
+        let Some((name, arg)) = parse(input) else {
+            return Vec::new();
+        };
+        if !input.contains(char::is_whitespace) {
+            return self
+                .commands
+                .iter()
+                .filter(|c| c.name.starts_with(name))
+                .map(|c| format!("/{} ", c.name))
+                .collect();
+        }
+        let Some(complete) = self.get(name).and_then(|c| c.complete) else {
+            return Vec::new();
+        };
+        complete(target, arg)
+            .into_iter()
+            .filter(|candidate| candidate.starts_with(arg))
+            .map(|candidate| format!("/{} {}", name, candidate))
+            .collect()
+    }
+}
+
+impl<T> Default for Registry<T> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
@@ -0,0 +1,76 @@
+#[cfg(test)]
+mod tests {
+    use crate::command::{self, Registry};
+
+    #[derive(Default)]
+    struct Target {
+        volume: u8,
+        names: Vec<String>,
+    }
+
+    fn registry() -> Registry<Target> {
+        Registry::<Target>::new()
+            .command("volume", "<level>", "set the volume", |t, arg| {
+                match arg.parse() {
+                    Ok(volume) => {
+                        t.volume = volume;
+                        format!("volume {}", volume)
+                    }
+                    Err(e) => e.to_string(),
+                }
+            })
+            .complete(|t, _| t.names.clone())
+            .command("view", "", "view it", |_, _| String::new())
+    }
+
+    #[test]
+    fn command_parse() {
+        assert_eq!(
+            command::parse("/set  temperature 0.2 "),
+            Some(("set", "temperature 0.2"))
+        );
+        assert_eq!(command::parse("/help"), Some(("help", "")));
+        assert_eq!(command::parse("//etc/hosts is"), None);
+        assert_eq!(command::parse("hello /set"), None);
+    }
+
+    #[test]
+    fn command_dispatch() {
+        let registry = registry();
+        let mut target = Target::default();
+        assert_eq!(
+            registry.dispatch(&mut target, "/volume 7").unwrap(),
+            "volume 7"
+        );
+        assert_eq!(target.volume, 7);
+        assert_eq!(
+            registry.dispatch(&mut target, "/mute").unwrap(),
+            "unknown command /mute, /help lists them, // sends it"
+        );
+        assert_eq!(registry.dispatch(&mut target, "turn it up"), None);
+        let names = registry.iter().map(|c| c.name).collect::<Vec<_>>();
+        assert_eq!(names, ["volume", "view"]);
+    }
+
+    #[test]
+    fn command_completion() {
+        let registry = registry();
+        let target = Target {
+            names: vec!["low".to_owned(), "loud".to_owned(), "high".to_owned()],
+            ..Default::default()
+        };
+        assert_eq!(registry.completions(&target, "/vi"), ["/view "]);
+        let both = registry.completions(&target, "/v");
+        assert_eq!(both, ["/volume ", "/view "]);
+        assert_eq!(command::common_prefix(&both), "/v");
+
+        let args = registry.completions(&target, "/volume lo");
+        assert_eq!(args, ["/volume low", "/volume loud"]);
+        assert_eq!(command::common_prefix(&args), "/volume lo");
+        assert!(registry.completions(&target, "/view x").is_empty());
+        assert_eq!(
+            command::common_prefix(&["äb".to_owned(), "äc".to_owned()]),
+            "ä"
+        );
+    }
+}
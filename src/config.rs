@@ -0,0 +1,432 @@
+use serde::Deserialize;
+
+use crate::cost::{Budget, Price};
+use std::{
+    collections::{hash_map::RandomState, BTreeMap},
+    env, fs,
+    hash::BuildHasher,
+    io::{Error, ErrorKind, Result},
+    path::{Path, PathBuf},
+    time::Duration,
+};
+
+// Config example (~/.config/llmi/config.toml):
+// ```toml
+// default = "groq"
+// theme = "base16-ocean.dark"
+// parallel = ["groq", "local"]
+// tools = ["read_file", "list_dir", "shell"]
+//
+// [params]
+// temperature = 0.7
+//
+// [profiles.groq]
+// provider = "openai"
+// endpoint = "https://api.groq.com/openai/v1/chat/completions"
+// api_key_env = "GROQ_API_KEY"
+// model = "llama-3.1-70b-versatile"
+// max_tokens = 3000
+// retry = { max_retries = 5, initial_delay_ms = 2000 }
+//
+// [profiles.claude]
+// provider = "anthropic"
+// api_key_env = "ANTHROPIC_API_KEY"
+// model = "claude-3-5-sonnet-20240620"
+//
+// [profiles.local]
+// provider = "ollama"
+// model = "llama3.1"
+// temperature = 0.7
+//
+// [personas.reviewer]
+// description = "terse code review"
+// prompt = "You review code. Point out bugs first, style last."
+//
+// [prices] # dollars per 1M tokens, by model
+// "llama-3.1-70b-versatile" = { input = 0.59, output = 0.79 }
+//
+// [budget]
+// daily = 5.0
+// session = 1.0
+// refuse = true
+// ```
+// Personas can also be kept as `personas/<name>.md` next to the config file,
+// the file content being the prompt.
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum ProviderKind {
+    #[default]
+    OpenAI,
+    Ollama,
+    Anthropic,
+    Gemini,
+}
+
+impl ProviderKind {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            ProviderKind::OpenAI => "openai",
+            ProviderKind::Ollama => "ollama",
+            ProviderKind::Anthropic => "anthropic",
+            ProviderKind::Gemini => "gemini",
+        }
+    }
+
+    /// whether its backend sends tools and their calls
+    pub fn supports_tools(&self) -> bool {
+        matches!(self, ProviderKind::OpenAI)
+    }
+
+    fn default_endpoint(&self) -> &'static str {
+        match self {
+            ProviderKind::OpenAI => "https://api.openai.com/v1/chat/completions",
+            ProviderKind::Ollama => "http://localhost:11434/api/chat",
+            ProviderKind::Anthropic => "https://api.anthropic.com/v1/messages",
+            ProviderKind::Gemini => "https://generativelanguage.googleapis.com/v1beta/models/{model}:streamGenerateContent?alt=sse",
+        }
+    }
+}
+
+#[derive(Debug, Clone, Default, Deserialize)]
+pub struct Profile {
+    /// filled from the table key
+    #[serde(skip)]
+    pub name: String,
+    #[serde(default)]
+    pub provider: ProviderKind,
+    pub endpoint: Option<String>,
+    pub api_key: Option<String>,
+    /// name of the environment variable holding the api key
+    pub api_key_env: Option<String>,
+    pub model: String,
+    /// `max_tokens`, `temperature` and friends right in the profile table
+    #[serde(flatten)]
+    pub params: GenerationParams,
+    #[serde(default)]
+    pub retry: RetryPolicy,
+    /// ask openai compatible servers for token usage while streaming with
+    /// `stream_options.include_usage`, on unless set to false for servers
+    /// rejecting it
+    pub stream_usage: Option<bool>,
+    /// false to ask for the whole answer at once, for servers without sse
+    pub stream: Option<bool>,
+}
+
+/// sampling and output settings, whatever is unset is left to the backend.
+/// config defaults are overridden per profile, those by `/set` at runtime.
+#[derive(Debug, Clone, Default, PartialEq, Deserialize)]
+pub struct GenerationParams {
+    pub max_tokens: Option<u32>,
+    pub temperature: Option<f32>,
+    pub top_p: Option<f32>,
+    pub stop: Option<Vec<String>>,
+    pub seed: Option<u64>,
+    pub presence_penalty: Option<f32>,
+    pub frequency_penalty: Option<f32>,
+    /// "json" to ask for a json object, "text" otherwise
+    pub response_format: Option<String>,
+}
+
+impl GenerationParams {
+    pub const NAMES: [&'static str; 8] = [
+        "max_tokens",
+        "temperature",
+        "top_p",
+        "stop",
+        "seed",
+        "presence_penalty",
+        "frequency_penalty",
+        "response_format",
+    ];
+
+    /// `self` with everything set in `other` taking precedence
+    pub fn merge(&self, other: &Self) -> Self {
+        Self {
+            max_tokens: other.max_tokens.or(self.max_tokens),
+            temperature: other.temperature.or(self.temperature),
+            top_p: other.top_p.or(self.top_p),
+            stop: other.stop.clone().or_else(|| self.stop.clone()),
+            seed: other.seed.or(self.seed),
+            presence_penalty: other.presence_penalty.or(self.presence_penalty),
+            frequency_penalty: other.frequency_penalty.or(self.frequency_penalty),
+            response_format: other
+                .response_format
+                .clone()
+                .or_else(|| self.response_format.clone()),
+        }
+    }
+
+    /// set `name` from its text form, stop sequences are comma separated
+    pub fn set(&mut self, name: &str, value: &str) -> std::result::Result<(), String> {
+        fn parse<T: std::str::FromStr>(name: &str, value: &str) -> std::result::Result<T, String> {
+            value
+                .parse()
+                .map_err(|_| format!("invalid {}: {}", name, value))
+        }
+        fn in_range(
+            name: &str,
+            value: &str,
+            min: f32,
+            max: f32,
+        ) -> std::result::Result<Option<f32>, String> {
+            let v: f32 = parse(name, value)?;
+            if !(min..=max).contains(&v) {
+                return Err(format!("{} must be between {} and {}", name, min, max));
+            }
+            Ok(Some(v))
+        }
+
+        match name {
+            "max_tokens" => self.max_tokens = Some(parse(name, value)?),
+            "temperature" => self.temperature = in_range(name, value, 0.0, 2.0)?,
+            "top_p" => self.top_p = in_range(name, value, 0.0, 1.0)?,
+            "stop" => self.stop = Some(value.split(',').map(String::from).collect()),
+            "seed" => self.seed = Some(parse(name, value)?),
+            "presence_penalty" => self.presence_penalty = in_range(name, value, -2.0, 2.0)?,
+            "frequency_penalty" => self.frequency_penalty = in_range(name, value, -2.0, 2.0)?,
+            "response_format" if value == "json" || value == "text" => {
+                self.response_format = Some(value.to_owned())
+            }
+            "response_format" => return Err("response_format is json or text".to_owned()),
+            _ => return Err(format!("unknown parameter {}", name)),
+        }
+        Ok(())
+    }
+
+    pub fn unset(&mut self, name: &str) -> std::result::Result<(), String> {
+        match name {
+            "max_tokens" => self.max_tokens = None,
+            "temperature" => self.temperature = None,
+            "top_p" => self.top_p = None,
+            "stop" => self.stop = None,
+            "seed" => self.seed = None,
+            "presence_penalty" => self.presence_penalty = None,
+            "frequency_penalty" => self.frequency_penalty = None,
+            "response_format" => self.response_format = None,
+            _ => return Err(format!("unknown parameter {}", name)),
+        }
+        Ok(())
+    }
+
+    /// `name=value` of everything set
+    pub fn describe(&self) -> Vec<String> {
+        let values = [
+            self.max_tokens.map(|v| v.to_string()),
+            self.temperature.map(|v| v.to_string()),
+            self.top_p.map(|v| v.to_string()),
+            self.stop.as_ref().map(|v| format!("{:?}", v.join(","))),
+            self.seed.map(|v| v.to_string()),
+            self.presence_penalty.map(|v| v.to_string()),
+            self.frequency_penalty.map(|v| v.to_string()),
+            self.response_format.clone(),
+        ];
+        Self::NAMES
+            .iter()
+            .zip(values)
+            .filter_map(|(name, value)| Some(format!("{}={}", name, value?)))
+            .collect()
+    }
+}
+
+/// how a request failing with 429, 5xx or a network error is retried,
+/// only ever before the answer starts streaming
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(default)]
+pub struct RetryPolicy {
+    /// retries after the first attempt, 0 disables retrying
+    pub max_retries: u32,
+    /// delay before the first retry, doubled for every further one
+    pub initial_delay_ms: u64,
+    pub max_delay_ms: u64,
+}
+
+impl Default for RetryPolicy {
+    fn default() -> Self {
+        Self {
+            max_retries: 3,
+            initial_delay_ms: 1000,
+            max_delay_ms: 30_000,
+        }
+    }
+}
+
+impl RetryPolicy {
+    /// exponential backoff for the `attempt`th retry (from 0), with the
+    /// upper half jittered so parallel clients don't retry in lockstep
+    pub fn delay(&self, attempt: u32) -> Duration {
+        let delay = self
+            .initial_delay_ms
+            .saturating_mul(1 << attempt.min(16))
+            .min(self.max_delay_ms);
+        let jitter = RandomState::new().hash_one(attempt) % (delay / 2 + 1);
+        Duration::from_millis(delay - jitter)
+    }
+}
+
+impl Profile {
+    /// `{model}` in the endpoint is replaced by the model name
+    pub fn endpoint(&self) -> String {
+        self.endpoint
+            .as_deref()
+            .unwrap_or(self.provider.default_endpoint())
+            .replace("{model}", &self.model)
+    }
+
+    /// the literal `api_key` wins over `api_key_env`
+    pub fn api_key(&self) -> String {
+        self.api_key
+            .clone()
+            .or_else(|| {
+                self.api_key_env
+                    .as_deref()
+                    .and_then(|name| env::var(name).ok())
+            })
+            .unwrap_or_default()
+    }
+}
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct Config {
+    /// name of the profile active on startup
+    pub default: Option<String>,
+    /// syntect theme for code blocks, a default theme name or a `.tmTheme` path
+    pub theme: Option<String>,
+    /// profiles every prompt is sent to concurrently
+    #[serde(default)]
+    pub parallel: Vec<String>,
+    /// generation parameters of every profile, unless it sets its own
+    #[serde(default)]
+    pub params: GenerationParams,
+    #[serde(default)]
+    pub profiles: BTreeMap<String, Profile>,
+    /// persona of new sessions
+    pub persona: Option<String>,
+    #[serde(default)]
+    pub personas: BTreeMap<String, Persona>,
+    #[serde(default)]
+    pub prices: BTreeMap<String, Price>,
+    #[serde(default)]
+    pub budget: Budget,
+    /// built-in tools the models may call, none unless listed
+    #[serde(default)]
+    pub tools: Vec<String>,
+}
+
+/// a named system prompt
+#[derive(Debug, Clone, Default, Deserialize)]
+pub struct Persona {
+    /// filled from the table key or file name
+    #[serde(skip)]
+    pub name: String,
+    pub description: Option<String>,
+    pub prompt: String,
+}
+
+impl Config {
+    /// `$LLMI_CONFIG` or `<config dir>/llmi/config.toml`
+    pub fn path() -> Option<PathBuf> {
+        env::var_os("LLMI_CONFIG")
+            .map(PathBuf::from)
+            .or_else(|| dirs::config_dir().map(|dir| dir.join("llmi").join("config.toml")))
+    }
+
+    /// load the config file, falling back to the `LLM_*` environment
+    /// variables when there is none.
+    pub fn load() -> Result<Self> {
+        match Self::path() {
+            Some(path) if path.exists() => {
+                let content = fs::read_to_string(&path)?;
+                let mut config = Self::parse(&content).map_err(|e| {
+                    Error::new(ErrorKind::InvalidData, format!("{}: {}", path.display(), e))
+                })?;
+                if let Some(dir) = path.parent() {
+                    config.load_personas(&dir.join("personas"))?;
+                }
+                Ok(config)
+            }
+            _ => Ok(Self::from_env()),
+        }
+    }
+
+    /// add every `<name>.md` or `<name>.txt` in `dir` as a persona, those
+    /// from the config file win
+    pub fn load_personas(&mut self, dir: &Path) -> Result<()> {
+        if !dir.is_dir() {
+            return Ok(());
+        }
+        for entry in fs::read_dir(dir)? {
+            let path = entry?.path();
+            let is_text = path
+                .extension()
+                .is_some_and(|ext| ext == "md" || ext == "txt");
+            let Some(name) = path.file_stem().and_then(|s| s.to_str()) else {
+                continue;
+            };
+            if !is_text || self.personas.contains_key(name) {
+                continue;
+            }
+            let persona = Persona {
+                name: name.to_owned(),
+                description: None,
+                prompt: fs::read_to_string(&path)?.trim().to_owned(),
+            };
+            self.personas.insert(name.to_owned(), persona);
+        }
+        Ok(())
+    }
+
+    pub fn parse(content: &str) -> Result<Self> {
+        let mut config: Config =
+            toml::from_str(content).map_err(|e| Error::new(ErrorKind::InvalidData, e))?;
+        if config.profiles.is_empty() {
+            config.profiles = Self::from_env().profiles;
+        }
+        for (name, profile) in config.profiles.iter_mut() {
+            profile.name = name.clone();
+        }
+        for (name, persona) in config.personas.iter_mut() {
+            persona.name = name.clone();
+        }
+        Ok(config)
+    }
+
+    /// a single profile named "default" built from `LLM_PROVIDER`,
+    /// `LLM_ENDPOINT`, `LLM_API_KEY` and `LLM_MODEL`
+    pub fn from_env() -> Self {
+        let provider = match env::var("LLM_PROVIDER").as_deref() {
+            Ok("ollama") => ProviderKind::Ollama,
+            Ok("anthropic") => ProviderKind::Anthropic,
+            Ok("gemini") => ProviderKind::Gemini,
+            _ => ProviderKind::OpenAI,
+        };
+        let model = env::var("LLM_MODEL").unwrap_or_else(|_| match provider {
+            ProviderKind::OpenAI => "mixtral-8x7b-32768".to_owned(),
+            ProviderKind::Ollama => "llama3.1".to_owned(),
+            ProviderKind::Anthropic => "claude-3-5-sonnet-20240620".to_owned(),
+            ProviderKind::Gemini => "gemini-1.5-flash".to_owned(),
+        });
+
+        let profile = Profile {
+            name: "default".to_owned(),
+            provider,
+            endpoint: env::var("LLM_ENDPOINT").ok(),
+            api_key_env: Some("LLM_API_KEY".to_owned()),
+            model,
+            ..Default::default()
+        };
+
+        Self {
+            default: None,
+            theme: None,
+            parallel: Vec::new(),
+            params: GenerationParams::default(),
+            profiles: BTreeMap::from([(profile.name.clone(), profile)]),
+            persona: None,
+            personas: BTreeMap::new(),
+            prices: BTreeMap::new(),
+            budget: Budget::default(),
+            tools: Vec::new(),
+        }
+    }
+}
@@ -0,0 +1,178 @@
+use serde::{Deserialize, Serialize};
+
+use crate::llm::Message;
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+struct Node {
+    message: Message,
+    #[serde(default)]
+    children: Vec<usize>,
+    /// which of the children the active branch continues with
+    #[serde(default)]
+    active: usize,
+}
+
+/// messages stored as a tree, resending an edited prompt starts a new branch
+/// next to the old one instead of throwing it away. everything else sees the
+/// active branch only, a list of messages from the first prompt on.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(from = "Repr")]
+pub struct Conversation {
+    nodes: Vec<Node>,
+    /// first messages of every branch that starts at the very top
+    roots: Vec<usize>,
+    active: usize,
+}
+
+/// sessions saved before branching existed hold a plain list
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum Repr {
+    Tree {
+        nodes: Vec<Node>,
+        roots: Vec<usize>,
+        active: usize,
+    },
+    List(Vec<Message>),
+}
+
+impl From<Repr> for Conversation {
+    fn from(repr: Repr) -> Self {
+        match repr {
+            Repr::Tree {
+                nodes,
+                roots,
+                active,
+            } => Self {
+                nodes,
+                roots,
+                active,
+            },
+            Repr::List(messages) => {
+                let mut conversation = Self::default();
+                messages.into_iter().for_each(|msg| conversation.push(msg));
+                conversation
+            }
+        }
+    }
+}
+
+impl Conversation {
+    /// node ids of the active branch
+    fn path(&self) -> Vec<usize> {
+        let mut path = Vec::new();
+        let (mut level, mut active) = (&self.roots, self.active);
+        while let Some(&id) = level.get(active) {
+            path.push(id);
+            level = &self.nodes[id].children;
+            active = self.nodes[id].active;
+        }
+        path
+    }
+
+    /// the ids of the node at position `i` of the active branch and its siblings
+    fn siblings(&self, i: usize) -> &[usize] {
+        match i.checked_sub(1) {
+            Some(parent) => &self.nodes[self.path()[parent]].children,
+            None => &self.roots,
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.path().len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.roots.is_empty()
+    }
+
+    /// messages of the active branch
+    pub fn iter(&self) -> impl DoubleEndedIterator<Item = &Message> + ExactSizeIterator {
+        self.path().into_iter().map(|id| &self.nodes[id].message)
+    }
+
+    /// copies of the active branch, with `branch` set on the messages that
+    /// have alternatives
+    pub fn messages(&self) -> Vec<Message> {
+        let mut messages = Vec::new();
+        let (mut level, mut active) = (&self.roots, self.active);
+        while let Some(&id) = level.get(active) {
+            let mut msg = self.nodes[id].message.clone();
+            if level.len() > 1 {
+                msg.branch = Some((active, level.len()));
+            }
+            messages.push(msg);
+            level = &self.nodes[id].children;
+            active = self.nodes[id].active;
+        }
+        messages
+    }
+
+    /// every message, the ones on other branches included
+    pub fn all(&self) -> impl Iterator<Item = &Message> {
+        self.nodes.iter().map(|node| &node.message)
+    }
+
+    pub fn get(&self, i: usize) -> Option<&Message> {
+        self.path().get(i).map(|&id| &self.nodes[id].message)
+    }
+
+    pub fn push(&mut self, msg: Message) {
+        self.insert(self.len(), msg);
+    }
+
+    /// continue the active branch after its first `i` messages with `msg`,
+    /// anything that followed stays reachable as a sibling branch
+    pub fn insert(&mut self, i: usize, msg: Message) {
+        let path = self.path();
+        let id = self.nodes.len();
+        self.nodes.push(Node {
+            message: msg,
+            children: Vec::new(),
+            active: 0,
+        });
+
+        let (level, active) = match i.min(path.len()).checked_sub(1) {
+            Some(parent) => {
+                let parent = &mut self.nodes[path[parent]];
+                (&mut parent.children, &mut parent.active)
+            }
+            None => (&mut self.roots, &mut self.active),
+        };
+        level.push(id);
+        *active = level.len() - 1;
+    }
+
+    /// position of message `i` among its siblings and how many there are
+    pub fn branch(&self, i: usize) -> (usize, usize) {
+        let path = self.path();
+        let siblings = self.siblings(i);
+        let pos = siblings.iter().position(|&id| Some(&id) == path.get(i));
+        (pos.unwrap_or(0), siblings.len())
+    }
+
+    /// make the next (or previous with a negative `delta`) sibling of
+    /// message `i` part of the active branch, false if there is none
+    pub fn switch(&mut self, i: usize, delta: isize) -> bool {
+        let (pos, count) = self.branch(i);
+        let Some(pos) = pos.checked_add_signed(delta).filter(|pos| *pos < count) else {
+            return false;
+        };
+        match i.checked_sub(1) {
+            Some(parent) => {
+                let parent = self.path()[parent];
+                self.nodes[parent].active = pos;
+            }
+            None => self.active = pos,
+        }
+        true
+    }
+}
+
+impl std::ops::Index<usize> for Conversation {
+    type Output = Message;
+
+    fn index(&self, i: usize) -> &Message {
+        self.get(i).expect("message index out of range")
+    }
+}
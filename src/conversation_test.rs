@@ -0,0 +1,65 @@
+#[cfg(test)]
+mod tests {
+    use crate::conversation::Conversation;
+    use crate::llm::Message;
+
+    fn contents(conversation: &Conversation) -> Vec<String> {
+        conversation
+            .iter()
+            .map(|msg| msg.content.clone().unwrap_or_default())
+            .collect()
+    }
+
+    #[test]
+    fn conversation_branches() {
+        let mut conversation = Conversation::default();
+        conversation.push(Message::user("hi".to_owned()));
+        conversation.push(Message::assistant("hello".to_owned()));
+        conversation.push(Message::user("tell a joke".to_owned()));
+        conversation.push(Message::assistant("no".to_owned()));
+
+        // resend an edited second prompt
+        conversation.insert(2, Message::user("tell a short joke".to_owned()));
+        conversation.push(Message::assistant("knock knock".to_owned()));
+        assert_eq!(
+            contents(&conversation),
+            vec!["hi", "hello", "tell a short joke", "knock knock"]
+        );
+        assert_eq!(conversation.branch(2), (1, 2));
+        assert_eq!(conversation.messages()[2].branch, Some((1, 2)));
+        assert_eq!(conversation.messages()[1].branch, None);
+
+        // the old continuation is still there
+        assert!(!conversation.switch(2, 1));
+        assert!(conversation.switch(2, -1));
+        assert_eq!(
+            contents(&conversation),
+            vec!["hi", "hello", "tell a joke", "no"]
+        );
+
+        // edit the very first prompt
+        conversation.insert(0, Message::user("hey".to_owned()));
+        assert_eq!(contents(&conversation), vec!["hey"]);
+        assert_eq!(conversation.branch(0), (1, 2));
+        assert!(conversation.switch(0, -1));
+        assert_eq!(conversation.len(), 4);
+    }
+
+    #[test]
+    fn conversation_serde() {
+        let mut conversation = Conversation::default();
+        conversation.push(Message::user("a".to_owned()));
+        conversation.push(Message::assistant("b".to_owned()));
+        conversation.insert(0, Message::user("c".to_owned()));
+
+        let json = serde_json::to_string(&conversation).unwrap();
+        let loaded: Conversation = serde_json::from_str(&json).unwrap();
+        assert_eq!(contents(&loaded), vec!["c"]);
+        assert_eq!(loaded.branch(0), (1, 2));
+
+        // sessions saved before branching hold a plain list
+        let legacy = r#"[{"role":"user","content":"a"},{"role":"assistant","content":"b"}]"#;
+        let loaded: Conversation = serde_json::from_str(legacy).unwrap();
+        assert_eq!(contents(&loaded), vec!["a", "b"]);
+    }
+}
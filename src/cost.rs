@@ -0,0 +1,106 @@
+use chrono::Local;
+use serde::Deserialize;
+use std::{
+    collections::BTreeMap,
+    fs,
+    io::Result,
+    path::{Path, PathBuf},
+};
+
+use crate::session;
+
+/// dollars per million tokens
+#[derive(Debug, Clone, Copy, Default, PartialEq, Deserialize)]
+pub struct Price {
+    pub input: f64,
+    pub output: f64,
+}
+
+impl Price {
+    pub fn cost(&self, prompt_tokens: u64, completion_tokens: u64) -> f64 {
+        (prompt_tokens as f64 * self.input + completion_tokens as f64 * self.output) / 1e6
+    }
+}
+
+/// the price of `model`, a dated snapshot like `gpt-4o-2024-08-06` falls
+/// back to the longest listed prefix
+pub fn price_of<'a>(prices: &'a BTreeMap<String, Price>, model: &str) -> Option<&'a Price> {
+    prices
+        .iter()
+        .filter(|(name, _)| model.starts_with(name.as_str()))
+        .max_by_key(|(name, _)| name.len())
+        .map(|(_, price)| price)
+}
+
+/// spending limits in dollars, a prompt is only warned about unless `refuse`
+#[derive(Debug, Clone, Default, Deserialize)]
+pub struct Budget {
+    pub daily: Option<f64>,
+    pub session: Option<f64>,
+    #[serde(default)]
+    pub refuse: bool,
+}
+
+/// "$0.0031" below a cent, "$1.25" above
+pub fn dollars(amount: f64) -> String {
+    if amount > 0.0 && amount < 0.01 {
+        format!("${:.4}", amount)
+    } else {
+        format!("${:.2}", amount)
+    }
+}
+
+/// dollars spent per day by every session, kept as `<data dir>/ledger.json`
+#[derive(Debug, Default)]
+pub struct Ledger {
+    path: Option<PathBuf>,
+    days: BTreeMap<String, f64>,
+}
+
+impl Ledger {
+    pub fn load() -> Self {
+        match session::data_dir() {
+            Ok(dir) => Self::load_from(&dir.join("ledger.json")),
+            Err(_) => Self::default(),
+        }
+    }
+
+    /// a missing or unreadable file starts an empty ledger
+    pub fn load_from(path: &Path) -> Self {
+        let days = fs::read_to_string(path)
+            .ok()
+            .and_then(|content| serde_json::from_str(&content).ok())
+            .unwrap_or_default();
+        Self {
+            path: Some(path.to_owned()),
+            days,
+        }
+    }
+
+    fn today_key() -> String {
+        Local::now().format("%Y-%m-%d").to_string()
+    }
+
+    pub fn today(&self) -> f64 {
+        self.days
+            .get(&Self::today_key())
+            .copied()
+            .unwrap_or_default()
+    }
+
+    /// book `amount` on today, re-reading the file first as other instances
+    /// may have added to it meanwhile
+    pub fn add(&mut self, amount: f64) -> Result<()> {
+        let Some(path) = self.path.clone() else {
+            *self.days.entry(Self::today_key()).or_default() += amount;
+            return Ok(());
+        };
+        *self = Self::load_from(&path);
+        *self.days.entry(Self::today_key()).or_default() += amount;
+
+        if let Some(dir) = path.parent() {
+            fs::create_dir_all(dir)?;
+        }
+        fs::write(&path, serde_json::to_string_pretty(&self.days)?)
+    }
+}
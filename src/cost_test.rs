@@ -0,0 +1,66 @@
+#[cfg(test)]
+mod tests {
+    use crate::config::Config;
+    use crate::cost::{self, Ledger, Price};
+    use crate::llm::{Message, Stats};
+    use crate::session::Session;
+
+    #[test]
+    fn cost_prices() {
+        let config = Config::parse(
+            r#"
+            [prices]
+            "gpt-4o" = { input = 2.5, output = 10.0 }
+            "gpt-4o-mini" = { input = 0.15, output = 0.6 }
+
+            [budget]
+            daily = 5.0
+            refuse = true
+            "#,
+        )
+        .unwrap();
+        assert_eq!(config.budget.daily, Some(5.0));
+        assert!(config.budget.refuse);
+
+        let price = cost::price_of(&config.prices, "gpt-4o-mini-2024-07-18").unwrap();
+        assert_eq!(price.input, 0.15);
+        let price = cost::price_of(&config.prices, "gpt-4o-2024-08-06").unwrap();
+        assert_eq!(price.cost(1_000_000, 100_000), 3.5);
+        assert!(cost::price_of(&config.prices, "llama3.1").is_none());
+
+        assert_eq!(cost::dollars(3.5), "$3.50");
+        assert_eq!(cost::dollars(0.00042), "$0.0004");
+
+        let mut session = Session::new();
+        for cost in [0.25, 0.5] {
+            let mut msg = Message::assistant("answer".to_owned());
+            msg.stats = Some(Stats {
+                prompt_tokens: Some(10),
+                completion_tokens: Some(20),
+                cost: Some(cost),
+                ..Default::default()
+            });
+            session.conversation.push(msg);
+        }
+        assert_eq!(session.cost(), 0.75);
+        assert_eq!(session.tokens(), (20, 40));
+    }
+
+    #[test]
+    fn cost_ledger() {
+        let path = std::env::temp_dir().join(format!("llmi-ledger-{}.json", std::process::id()));
+        let mut ledger = Ledger::load_from(&path);
+        assert_eq!(ledger.today(), 0.0);
+        ledger.add(0.5).unwrap();
+
+        // another instance books on the same file
+        let mut other = Ledger::load_from(&path);
+        other.add(0.25).unwrap();
+        ledger.add(1.0).unwrap();
+        assert_eq!(ledger.today(), 1.75);
+        assert_eq!(Ledger::load_from(&path).today(), 1.75);
+        std::fs::remove_file(path).unwrap();
+
+        assert_eq!(Price::default().cost(100, 100), 0.0);
+    }
+}
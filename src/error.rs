@@ -0,0 +1,179 @@
+use reqwest::{header::RETRY_AFTER, Response, StatusCode};
+use serde_json::Value;
+use std::{fmt, time::Duration};
+
+/// why a request to a backend failed
+#[derive(Debug, Clone, PartialEq)]
+pub enum LLMError {
+    /// could not connect or the connection broke
+    Network(String),
+    /// non-2xx answer not covered by the variants below
+    Status {
+        code: u16,
+        body: String,
+    },
+    /// 401 or 403, usually a missing or wrong api key
+    Auth {
+        code: u16,
+        body: String,
+    },
+    /// 429, `retry_after` from the `Retry-After` header if sent
+    RateLimit {
+        retry_after: Option<Duration>,
+        body: String,
+    },
+    /// the response could not be understood
+    Parse(String),
+    /// an error reported inside an otherwise successful response
+    Api(String),
+    /// the prompt or answer was withheld by the provider's safety filters
+    Blocked(String),
+    Timeout,
+    /// the request task panicked, a bug rather than a server problem
+    Crashed(String),
+}
+
+impl LLMError {
+    /// turn a non-2xx response into an error, keeping the body for display
+    pub async fn from_response(resp: Response) -> Self {
+        let status = resp.status();
+        let retry_after = resp
+            .headers()
+            .get(RETRY_AFTER)
+            .and_then(|v| v.to_str().ok())
+            .and_then(|v| v.trim().parse::<u64>().ok())
+            .map(Duration::from_secs);
+        let body = resp.text().await.unwrap_or_default();
+
+        match status {
+            StatusCode::UNAUTHORIZED | StatusCode::FORBIDDEN => LLMError::Auth {
+                code: status.as_u16(),
+                body,
+            },
+            StatusCode::TOO_MANY_REQUESTS => LLMError::RateLimit { retry_after, body },
+            _ => LLMError::Status {
+                code: status.as_u16(),
+                body,
+            },
+        }
+    }
+
+    /// worth trying again: rate limits, server errors and network trouble
+    pub fn is_transient(&self) -> bool {
+        match self {
+            LLMError::RateLimit { .. } | LLMError::Network(_) | LLMError::Timeout => true,
+            LLMError::Status { code, .. } => *code >= 500,
+            _ => false,
+        }
+    }
+
+    /// a few words for the status bar
+    pub fn brief(&self) -> String {
+        match self {
+            LLMError::Network(_) => "network error".to_owned(),
+            LLMError::Status { code, .. } | LLMError::Auth { code, .. } => format!("HTTP {}", code),
+            LLMError::RateLimit { .. } => "rate limited".to_owned(),
+            LLMError::Parse(_) => "invalid response".to_owned(),
+            LLMError::Api(_) => "server error".to_owned(),
+            LLMError::Blocked(_) => "blocked".to_owned(),
+            LLMError::Timeout => "timed out".to_owned(),
+            LLMError::Crashed(_) => "crashed".to_owned(),
+        }
+    }
+
+    /// http status code, if the server answered at all
+    pub fn code(&self) -> Option<u16> {
+        match self {
+            LLMError::Status { code, .. } | LLMError::Auth { code, .. } => Some(*code),
+            LLMError::RateLimit { .. } => Some(429),
+            _ => None,
+        }
+    }
+}
+
+/// the `error.message` (openai, anthropic), `error` (ollama) or
+/// `[0].error.message` (gemini) of a json error body, else the body itself
+fn server_message(body: &str) -> String {
+    let message = serde_json::from_str::<Value>(body).ok().and_then(|v| {
+        let v = v.get(0).unwrap_or(&v).clone();
+        match v.get("error") {
+            Some(Value::String(s)) => Some(s.clone()),
+            Some(e) => e.get("message").and_then(|m| m.as_str()).map(String::from),
+            None => v.get("message").and_then(|m| m.as_str()).map(String::from),
+        }
+    });
+    message.unwrap_or_else(|| body.trim().to_owned())
+}
+
+impl fmt::Display for LLMError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            LLMError::Network(e) => write!(f, "network error: {}", e),
+            LLMError::Status { code, body } => write!(f, "HTTP {}: {}", code, server_message(body)),
+            LLMError::Auth { code, body } => write!(
+                f,
+                "HTTP {} authentication failed: {}",
+                code,
+                server_message(body)
+            ),
+            LLMError::RateLimit { retry_after, body } => {
+                write!(f, "HTTP 429 rate limited: {}", server_message(body))?;
+                if let Some(after) = retry_after {
+                    write!(f, " (retry after {}s)", after.as_secs())?;
+                }
+                Ok(())
+            }
+            LLMError::Parse(e) => write!(f, "invalid response: {}", e),
+            LLMError::Api(e) => write!(f, "server error: {}", e),
+            LLMError::Blocked(e) => write!(f, "blocked by safety filters: {}", e),
+            LLMError::Timeout => write!(f, "request timed out"),
+            LLMError::Crashed(e) => write!(f, "request crashed: {}", e),
+        }
+    }
+}
+
+impl std::error::Error for LLMError {}
+
+impl From<tokio::task::JoinError> for LLMError {
+    fn from(e: tokio::task::JoinError) -> Self {
+        let Ok(panic) = e.try_into_panic() else {
+            return LLMError::Crashed("cancelled".to_owned());
+        };
+        let message = panic
+            .downcast_ref::<&str>()
+            .map(|s| s.to_string())
+            .or_else(|| panic.downcast_ref::<String>().cloned());
+        LLMError::Crashed(message.unwrap_or_else(|| "panicked".to_owned()))
+    }
+}
+
+impl From<reqwest::Error> for LLMError {
+    fn from(e: reqwest::Error) -> Self {
+        if e.is_timeout() {
+            LLMError::Timeout
+        } else if e.is_decode() {
+            LLMError::Parse(e.to_string())
+        } else {
+            // the top level message is vague, the cause is in the sources
+            let mut message = e.to_string();
+            let mut source = std::error::Error::source(&e);
+            while let Some(cause) = source {
+                message = format!("{}: {}", message, cause);
+                source = cause.source();
+            }
+            LLMError::Network(message)
+        }
+    }
+}
+
+impl From<serde_json::Error> for LLMError {
+    fn from(e: serde_json::Error) -> Self {
+        LLMError::Parse(e.to_string())
+    }
+}
+
+impl From<std::str::Utf8Error> for LLMError {
+    fn from(e: std::str::Utf8Error) -> Self {
+        LLMError::Parse(e.to_string())
+    }
+}
@@ -1,4 +1,7 @@
-use crate::llm::Message;
+use crate::{
+    error::LLMError,
+    llm::{Message, Stats, ToolCall},
+};
 use crossterm::event::Event as CrosstermEvent;
 use futures::{FutureExt, StreamExt};
 use std::{io::Result, time::Duration};
@@ -8,12 +11,26 @@ use tokio::{
     task::JoinHandle,
 };
 
+/// identifies one streamed answer, so deltas of requests running in
+/// parallel can be told apart
+pub type StreamId = usize;
+
 #[derive(Debug, Clone)]
 pub enum Event {
     TermEvent(CrosstermEvent),
-    LLMEventStart,
-    LLMEventDelta(Message),
-    LLMEventEnd,
+    LLMEventStart(StreamId),
+    LLMEventDelta(StreamId, Message),
+    LLMEventEnd(StreamId),
+    /// the request was cancelled, whatever arrived so far is kept
+    LLMEventCancelled(StreamId),
+    /// the request failed, this ends the stream
+    LLMEventError(StreamId, LLMError),
+    /// the request failed but is tried again, shown until the next delta
+    LLMEventRetry(StreamId, String),
+    /// token counts and timing, sent right before `LLMEventEnd`
+    LLMEventStats(StreamId, Stats),
+    /// output of a tool the model called
+    ToolResult(ToolCall, String),
     TickEvent,
     Notification(String),
 }
@@ -74,3 +91,15 @@ impl EventManager {
         })
     }
 }
+
+impl Default for EventManager {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Drop for EventManager {
+    fn drop(&mut self) {
+        self.handler.abort();
+    }
+}
@@ -0,0 +1,261 @@
+use async_trait::async_trait;
+use futures::Stream;
+use reqwest::{header::CONTENT_TYPE, Client};
+use serde::Deserialize;
+use serde_json::{json, Value};
+use tokio::sync::mpsc::UnboundedSender;
+use tokio_util::sync::CancellationToken;
+
+use crate::config::{GenerationParams, Profile};
+use crate::error::LLMError;
+use crate::event::{Event, StreamId};
+use crate::llm::*;
+use crate::sse;
+use crate::tool::ToolSpec;
+
+// `streamGenerateContent?alt=sse` sends one response per sse event:
+// ```json
+// {
+//     "candidates":[{
+//         "content":{"parts":[{"text":"The sky"}],"role":"model"},
+//         "finishReason":"STOP",
+//         "index":0,
+//         "safetyRatings":[{"category":"HARM_CATEGORY_HARASSMENT","probability":"NEGLIGIBLE"}]
+//     }],
+//     "usageMetadata":{"promptTokenCount":8,"candidatesTokenCount":9,"totalTokenCount":17},
+//     "modelVersion":"gemini-1.5-flash"
+// }
+// ```
+// a blocked prompt comes without candidates but with
+// `"promptFeedback":{"blockReason":"SAFETY","safetyRatings":[..]}`.
+#[derive(Debug, Default, Deserialize)]
+#[serde(rename_all = "camelCase", default)]
+struct Chunk {
+    candidates: Vec<Candidate>,
+    prompt_feedback: Option<PromptFeedback>,
+    usage_metadata: Option<UsageMetadata>,
+    error: Option<Value>,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct Candidate {
+    content: Option<Content>,
+    finish_reason: Option<String>,
+    #[serde(default)]
+    safety_ratings: Vec<SafetyRating>,
+}
+
+#[derive(Debug, Deserialize)]
+struct Content {
+    #[serde(default)]
+    parts: Vec<Part>,
+}
+
+#[derive(Debug, Deserialize)]
+struct Part {
+    text: Option<String>,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct PromptFeedback {
+    block_reason: Option<String>,
+    #[serde(default)]
+    safety_ratings: Vec<SafetyRating>,
+}
+
+#[derive(Debug, Deserialize)]
+struct SafetyRating {
+    category: String,
+    #[serde(default)]
+    probability: String,
+    #[serde(default)]
+    blocked: bool,
+}
+
+#[derive(Debug, Default, Deserialize)]
+#[serde(rename_all = "camelCase", default)]
+struct UsageMetadata {
+    prompt_token_count: u64,
+    candidates_token_count: u64,
+    total_token_count: u64,
+}
+
+/// finish reasons meaning the answer was withheld, not just ended
+const BLOCKED: [&str; 5] = [
+    "SAFETY",
+    "RECITATION",
+    "BLOCKLIST",
+    "PROHIBITED_CONTENT",
+    "SPII",
+];
+
+/// `reason` with the categories that triggered it, e.g.
+/// "SAFETY (HARM_CATEGORY_DANGEROUS_CONTENT)"
+fn blocked(reason: &str, ratings: &[SafetyRating]) -> LLMError {
+    let mut categories = ratings
+        .iter()
+        .filter(|r| r.blocked || matches!(r.probability.as_str(), "MEDIUM" | "HIGH"))
+        .map(|r| r.category.as_str())
+        .collect::<Vec<_>>();
+    categories.dedup();
+    if categories.is_empty() {
+        LLMError::Blocked(reason.to_owned())
+    } else {
+        LLMError::Blocked(format!("{} ({})", reason, categories.join(", ")))
+    }
+}
+
+#[derive(Debug)]
+pub struct Gemini {
+    cli: Client,
+    profile: Profile,
+}
+
+impl Gemini {
+    pub fn new(profile: Profile) -> Self {
+        Self {
+            cli: http_client(),
+            profile,
+        }
+    }
+
+    /// answers are sent as the `model` role, system messages as
+    /// `systemInstruction`
+    pub(crate) fn body(&self, history: &[Message], params: &GenerationParams) -> Value {
+        let system = history
+            .iter()
+            .filter(|msg| msg.is_system())
+            .filter_map(|msg| msg.content.as_deref())
+            .map(|text| json!({ "text": text }))
+            .collect::<Vec<_>>();
+        let contents = history
+            .iter()
+            .filter(|msg| !msg.is_system())
+            .map(|msg| {
+                let role = if msg.is_assistant() { "model" } else { "user" };
+                json!({ "role": role, "parts": [{ "text": msg.content }] })
+            })
+            .collect::<Vec<_>>();
+
+        let mut data = json!({ "contents": contents });
+        if !system.is_empty() {
+            data["systemInstruction"] = json!({ "parts": system });
+        }
+        let fields = [
+            ("maxOutputTokens", json!(params.max_tokens)),
+            ("temperature", json!(params.temperature)),
+            ("topP", json!(params.top_p)),
+            ("stopSequences", json!(params.stop)),
+            ("seed", json!(params.seed)),
+            ("presencePenalty", json!(params.presence_penalty)),
+            ("frequencyPenalty", json!(params.frequency_penalty)),
+        ];
+        for (name, value) in fields {
+            if !value.is_null() {
+                data["generationConfig"][name] = value;
+            }
+        }
+        if params.response_format.as_deref() == Some("json") {
+            data["generationConfig"]["responseMimeType"] = json!("application/json");
+        }
+        data
+    }
+}
+
+#[async_trait]
+impl LLMService for Gemini {
+    async fn request(
+        &mut self,
+        id: StreamId,
+        history: Vec<Message>,
+        params: GenerationParams,
+        _tools: Vec<ToolSpec>,
+        tx: UnboundedSender<Event>,
+        cancel: CancellationToken,
+    ) -> Result<(), LLMError> {
+        let endpoint = self.profile.endpoint();
+        let api_key = self.profile.api_key();
+
+        let data = self.body(&history, &params);
+
+        tx.send(Event::LLMEventStart(id)).unwrap();
+
+        let timer = Timer::start();
+        let req = || {
+            self.cli
+                .post(&endpoint)
+                .header("x-goog-api-key", &api_key)
+                .header(CONTENT_TYPE, "application/json")
+                .json(&data)
+        };
+        let Some(resp) = send_with_retry(id, &self.profile.retry, req, &tx, &cancel).await? else {
+            tx.send(Event::LLMEventCancelled(id)).unwrap();
+            return Ok(());
+        };
+
+        read_stream(id, resp.bytes_stream(), timer, &tx, &cancel).await
+    }
+}
+
+/// forward the text of a `streamGenerateContent` sse body, a safety block
+/// is returned as `LLMError::Blocked` so it doesn't pass as an empty answer
+pub(crate) async fn read_stream<S, B, E>(
+    id: StreamId,
+    body: S,
+    timer: Timer,
+    tx: &UnboundedSender<Event>,
+    cancel: &CancellationToken,
+) -> Result<(), LLMError>
+where
+    S: Stream<Item = Result<B, E>>,
+    B: AsRef<[u8]>,
+    E: Into<LLMError>,
+{
+    read_events(id, sse::decode(body), timer, tx, cancel, |event, reply| {
+        if event.data.is_empty() {
+            return Ok(Flow::Continue);
+        }
+        let chunk = serde_json::from_str::<Chunk>(&event.data)?;
+        if let Some(error) = chunk.error {
+            let message = error.get("message").and_then(|m| m.as_str());
+            return Err(LLMError::Api(
+                message.map_or_else(|| error.to_string(), String::from),
+            ));
+        }
+        if let Some(feedback) = chunk.prompt_feedback {
+            if let Some(reason) = feedback.block_reason {
+                return Err(blocked(&reason, &feedback.safety_ratings));
+            }
+        }
+        // the counts are running totals, the last ones win
+        if let Some(meta) = chunk.usage_metadata {
+            reply.usage = Some(Usage {
+                prompt_tokens: meta.prompt_token_count,
+                completion_tokens: meta.candidates_token_count,
+                total_tokens: meta.total_token_count,
+                ..Default::default()
+            });
+        }
+
+        let Some(candidate) = chunk.candidates.into_iter().next() else {
+            return Ok(Flow::Continue);
+        };
+        let parts = candidate.content.map(|c| c.parts).unwrap_or_default();
+        let text = parts.into_iter().filter_map(|p| p.text).collect::<String>();
+        if !text.is_empty() {
+            reply.timer.token();
+            tx.send(Event::LLMEventDelta(id, Message::assistant(text)))
+                .unwrap();
+        }
+        if let Some(reason) = candidate.finish_reason {
+            if BLOCKED.contains(&reason.as_str()) {
+                return Err(blocked(&reason, &candidate.safety_ratings));
+            }
+            reply.stop_reason = Some(reason.to_lowercase());
+        }
+        Ok(Flow::Continue)
+    })
+    .await
+}
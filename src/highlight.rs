@@ -0,0 +1,137 @@
+use ratatui::{
+    style::{Color, Modifier, Style},
+    text::Span,
+};
+use std::{
+    cell::RefCell,
+    collections::{hash_map::DefaultHasher, HashMap},
+    hash::{Hash, Hasher},
+    io::{Error, ErrorKind, Result},
+    path::Path,
+    sync::OnceLock,
+};
+use syntect::{
+    easy::HighlightLines,
+    highlighting::{FontStyle, Theme, ThemeSet},
+    parsing::SyntaxSet,
+    util::LinesWithEndings,
+};
+
+pub const DEFAULT_THEME: &str = "base16-ocean.dark";
+
+static SYNTAXES: OnceLock<SyntaxSet> = OnceLock::new();
+static THEME: OnceLock<Theme> = OnceLock::new();
+
+thread_local! {
+    // highlighting is redone on every frame, finished code blocks don't change
+    static CACHE: RefCell<HashMap<u64, Vec<Vec<Span<'static>>>>> = RefCell::new(HashMap::new());
+}
+
+/// pick the theme by name (one of syntect's defaults) or by path to a
+/// `.tmTheme` file, must be called before anything is highlighted.
+pub fn init(theme: Option<&str>) -> Result<()> {
+    let name = theme.unwrap_or(DEFAULT_THEME);
+    let theme = if Path::new(name).is_file() {
+        ThemeSet::get_theme(name).map_err(|e| Error::new(ErrorKind::InvalidData, e))?
+    } else {
+        let mut themes = ThemeSet::load_defaults().themes;
+        themes.remove(name).ok_or_else(|| {
+            let known = themes.keys().cloned().collect::<Vec<_>>().join(", ");
+            Error::new(
+                ErrorKind::InvalidInput,
+                format!("unknown theme {}, try one of: {}", name, known),
+            )
+        })?
+    };
+
+    let _ = THEME.set(theme);
+    Ok(())
+}
+
+fn theme() -> &'static Theme {
+    THEME.get_or_init(|| {
+        ThemeSet::load_defaults()
+            .themes
+            .remove(DEFAULT_THEME)
+            .unwrap_or_default()
+    })
+}
+
+fn syntaxes() -> &'static SyntaxSet {
+    SYNTAXES.get_or_init(SyntaxSet::load_defaults_newlines)
+}
+
+/// background of code blocks
+pub fn background() -> Color {
+    theme()
+        .settings
+        .background
+        .map(|c| Color::Rgb(c.r, c.g, c.b))
+        .unwrap_or(Color::Indexed(236))
+}
+
+/// highlight `code` by the language tag of its fence, one entry per line.
+/// an unfinished last line (still streaming in) is highlighted as well.
+pub fn highlight(code: &str, lang: &str) -> Vec<Vec<Span<'static>>> {
+    let mut hasher = DefaultHasher::new();
+    (code, lang).hash(&mut hasher);
+    let key = hasher.finish();
+
+    if let Some(lines) = CACHE.with(|cache| cache.borrow().get(&key).cloned()) {
+        return lines;
+    }
+
+    let lines = highlight_lines(code, lang);
+    CACHE.with(|cache| {
+        let mut cache = cache.borrow_mut();
+        if cache.len() > 256 {
+            cache.clear();
+        }
+        cache.insert(key, lines.clone());
+    });
+    lines
+}
+
+fn highlight_lines(code: &str, lang: &str) -> Vec<Vec<Span<'static>>> {
+    let syntaxes = syntaxes();
+    let syntax = syntaxes
+        .find_syntax_by_token(lang)
+        .unwrap_or_else(|| syntaxes.find_syntax_plain_text());
+    let mut highlighter = HighlightLines::new(syntax, theme());
+    let bg = background();
+
+    let code = code.replace('\t', "    ");
+    LinesWithEndings::from(&code)
+        .map(|ln| match highlighter.highlight_line(ln, syntaxes) {
+            Ok(ranges) => ranges
+                .into_iter()
+                .map(|(style, text)| {
+                    let text = text.trim_end_matches(['\n', '\r']).to_owned();
+                    Span::styled(text, to_style(style).bg(bg))
+                })
+                .filter(|span| !span.content.is_empty())
+                .collect(),
+            Err(_) => vec![Span::styled(
+                ln.trim_end_matches(['\n', '\r']).to_owned(),
+                Style::new().bg(bg),
+            )],
+        })
+        .collect()
+}
+
+fn to_style(style: syntect::highlighting::Style) -> Style {
+    let fg = style.foreground;
+    let mut modifier = Modifier::empty();
+    if style.font_style.contains(FontStyle::BOLD) {
+        modifier |= Modifier::BOLD;
+    }
+    if style.font_style.contains(FontStyle::ITALIC) {
+        modifier |= Modifier::ITALIC;
+    }
+    if style.font_style.contains(FontStyle::UNDERLINE) {
+        modifier |= Modifier::UNDERLINED;
+    }
+    Style::new()
+        .fg(Color::Rgb(fg.r, fg.g, fg.b))
+        .add_modifier(modifier)
+}
@@ -1,7 +1,30 @@
+mod anthropic;
 pub mod app;
+pub mod attach;
 mod chatgpt;
+mod clipboard;
+pub mod command;
+pub mod config;
+pub mod conversation;
+pub mod cost;
+pub mod error;
 pub mod event;
+mod gemini;
+pub mod highlight;
 pub mod llm;
+mod markdown;
+mod ollama;
+pub mod session;
+mod sse;
 pub mod term;
+pub mod tool;
 
+mod attach_test;
+mod clipboard_test;
+mod command_test;
+mod conversation_test;
+mod cost_test;
 mod llm_test;
+mod markdown_test;
+mod session_test;
+mod tool_test;
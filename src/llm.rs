@@ -1,16 +1,39 @@
 use async_trait::async_trait;
+use futures::{pin_mut, Stream, StreamExt};
 use ratatui::{
     buffer::Buffer,
     layout::{Alignment, Rect},
-    style::Color,
+    style::{Color, Stylize},
     text::{Line, Text},
-    widgets::{Block, Borders, Paragraph, Widget, Wrap},
+    widgets::{Block, Borders, Paragraph, Widget},
 };
+use reqwest::{Client, RequestBuilder, Response};
 use serde::{Deserialize, Serialize};
-use std::io::Result;
-use tokio::sync::mpsc::UnboundedSender;
+use std::{
+    collections::{hash_map::DefaultHasher, HashMap},
+    hash::{Hash, Hasher},
+    sync::Arc,
+    time::{Duration, Instant},
+};
+use tokio::{
+    select,
+    sync::{mpsc::UnboundedSender, Mutex},
+};
+use tokio_util::sync::CancellationToken;
+use unicode_width::UnicodeWidthStr;
 
-use crate::{chatgpt::ChatGPT, event::Event};
+use crate::{
+    anthropic::Anthropic,
+    chatgpt::ChatGPT,
+    config::{Config, GenerationParams, Profile, ProviderKind, RetryPolicy},
+    cost,
+    error::LLMError,
+    event::{Event, StreamId},
+    gemini::Gemini,
+    markdown,
+    ollama::Ollama,
+    tool::ToolSpec,
+};
 
 // LLMResponse Example:
 // ```json
@@ -31,8 +54,13 @@ use crate::{chatgpt::ChatGPT, event::Event};
 //     "x_groq":{"id":"2eDfhFtOnQU6ukxwCD0f6HWsM45"}
 // }
 // ```
+// while streaming, groq sends the usage in `x_groq` of the last chunk and
+// openai in a last chunk without choices when asked to with
+// `stream_options.include_usage`.
 
+// some servers leave out the ids on usage-only or keep-alive chunks
 #[derive(Debug, Default, Serialize, Deserialize)]
+#[serde(default)]
 pub struct LLMResponse {
     id: String,
     object: String,
@@ -40,16 +68,24 @@ pub struct LLMResponse {
     model: String,
     pub(crate) choices: Vec<Choice>,
     pub(crate) usage: Option<Usage>,
+    x_groq: Option<XGroq>,
 }
 
 #[derive(Debug, Default, Serialize, Deserialize)]
+struct XGroq {
+    usage: Option<Usage>,
+}
+
+/// token counts, the timings are only reported by groq
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(default)]
 pub struct Usage {
-    prompt_tokens: u64,
-    prompt_time: f64,
-    completion_tokens: u64,
-    completion_time: f64,
-    total_tokens: u64,
-    total_time: f64,
+    pub prompt_tokens: u64,
+    pub prompt_time: f64,
+    pub completion_tokens: u64,
+    pub completion_time: f64,
+    pub total_tokens: u64,
+    pub total_time: f64,
 }
 
 #[derive(Debug, Serialize, Deserialize)]
@@ -61,10 +97,179 @@ pub struct Choice {
     finish_reason: Option<String>,
 }
 
-#[derive(Debug, Clone, Serialize, Deserialize)]
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
 pub struct Message {
     pub role: Option<String>,
     pub content: Option<String>,
+    /// name of the profile that produced this answer
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub provider: Option<String>,
+    /// the answer was stopped by the user before it was complete
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub interrupted: bool,
+    /// position among its sibling branches and their count, display only
+    #[serde(skip)]
+    pub branch: Option<(usize, usize)>,
+    /// token counts and speed of an answer
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub stats: Option<Stats>,
+    /// tools the model asks to run, or fragments of them while streaming
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub tool_calls: Option<Vec<ToolCall>>,
+    /// the call a `tool` message answers
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub tool_call_id: Option<String>,
+    /// the tool that produced a `tool` message
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub name: Option<String>,
+}
+
+/// rows of a tool result shown in the chat
+const TOOL_LINES: usize = 8;
+
+/// a function call requested by the model. streamed calls arrive in
+/// fragments sharing an `index`, only the first has the id and name.
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct ToolCall {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub index: Option<usize>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub id: Option<String>,
+    #[serde(rename = "type", default, skip_serializing_if = "Option::is_none")]
+    pub kind: Option<String>,
+    pub function: FunctionCall,
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct FunctionCall {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub name: Option<String>,
+    /// json object, as text
+    #[serde(default)]
+    pub arguments: String,
+}
+
+/// add streamed tool call `fragments` to `calls`, a fragment with the
+/// index of a call continues its arguments
+pub fn merge_tool_calls(calls: &mut Vec<ToolCall>, fragments: Vec<ToolCall>) {
+    for fragment in fragments {
+        let call = fragment
+            .index
+            .and_then(|index| calls.iter_mut().find(|c| c.index == Some(index)));
+        match call {
+            Some(call) => {
+                call.id = call.id.take().or(fragment.id);
+                call.kind = call.kind.take().or(fragment.kind);
+                call.function.name = call.function.name.take().or(fragment.function.name);
+                call.function
+                    .arguments
+                    .push_str(&fragment.function.arguments);
+            }
+            None => calls.push(fragment),
+        }
+    }
+}
+
+/// what an answer cost in tokens and how fast it came
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct Stats {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub prompt_tokens: Option<u64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub completion_tokens: Option<u64>,
+    /// seconds from sending the request to the first token
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub ttft: Option<f64>,
+    /// seconds from the first token to the last
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub generation: Option<f64>,
+    /// dollars, if the model has a price
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub cost: Option<f64>,
+    /// why the backend stopped, e.g. "max_tokens" or "length"
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub stop_reason: Option<String>,
+}
+
+impl Stats {
+    pub fn tokens_per_sec(&self) -> Option<f64> {
+        let tokens = self.completion_tokens? as f64;
+        self.generation
+            .filter(|secs| *secs > 0.0)
+            .map(|secs| tokens / secs)
+    }
+
+    /// e.g. "12+164 tok · $0.0002 · 567 tok/s · ttft 0.31s", empty if
+    /// nothing is known
+    pub fn describe(&self) -> String {
+        self.parts().join(" · ")
+    }
+
+    /// the pieces of `describe`, most important first
+    pub fn parts(&self) -> Vec<String> {
+        let mut parts = Vec::new();
+        match (self.prompt_tokens, self.completion_tokens) {
+            (Some(prompt), Some(completion)) => parts.push(format!("{prompt}+{completion} tok")),
+            (None, Some(completion)) => parts.push(format!("{completion} tok")),
+            _ => {}
+        }
+        if let Some(cost) = self.cost {
+            parts.push(cost::dollars(cost));
+        }
+        // a natural end is not worth mentioning
+        if let Some(reason) = self.stop_reason.as_deref() {
+            if !matches!(reason, "stop" | "end_turn" | "stop_sequence" | "tool_calls") {
+                parts.push(format!("stopped: {reason}"));
+            }
+        }
+        if let Some(speed) = self.tokens_per_sec() {
+            parts.push(format!("{speed:.0} tok/s"));
+        }
+        if let Some(ttft) = self.ttft {
+            parts.push(format!("ttft {ttft:.2}s"));
+        }
+        parts
+    }
+}
+
+/// clock of one request, started when it is sent
+pub(crate) struct Timer {
+    started: Instant,
+    first: Option<Instant>,
+}
+
+impl Timer {
+    pub(crate) fn start() -> Self {
+        Self {
+            started: Instant::now(),
+            first: None,
+        }
+    }
+
+    /// note that a token arrived
+    pub(crate) fn token(&mut self) {
+        self.first.get_or_insert_with(Instant::now);
+    }
+
+    /// the measured times merged with the `usage` the backend reported,
+    /// its own generation time is preferred over ours
+    pub(crate) fn stats(&self, usage: Option<&Usage>) -> Stats {
+        let first = self.first;
+        let mut stats = Stats {
+            ttft: first.map(|first| (first - self.started).as_secs_f64()),
+            generation: first.map(|first| first.elapsed().as_secs_f64()),
+            ..Default::default()
+        };
+        if let Some(usage) = usage {
+            stats.prompt_tokens = Some(usage.prompt_tokens);
+            stats.completion_tokens = Some(usage.completion_tokens);
+            if usage.completion_time > 0.0 {
+                stats.generation = Some(usage.completion_time);
+            }
+        }
+        stats
+    }
 }
 
 impl LLMResponse {
@@ -72,12 +277,22 @@ impl LLMResponse {
         LLMResponse::default()
     }
 
-    pub fn extract_message(&self) -> Message {
-        if self.choices[0].message.is_some() {
-            self.choices[0].message.clone().unwrap()
-        } else {
-            self.choices[0].delta.clone().unwrap()
-        }
+    /// why the backend stopped, sent with the last choice
+    pub fn finish_reason(&self) -> Option<&str> {
+        self.choices.first()?.finish_reason.as_deref()
+    }
+
+    /// the usage wherever the backend put it
+    pub fn usage(&self) -> Option<&Usage> {
+        self.usage
+            .as_ref()
+            .or_else(|| self.x_groq.as_ref()?.usage.as_ref())
+    }
+
+    /// the message or delta of the first choice, `None` without either
+    pub fn extract_message(&self) -> Option<Message> {
+        let choice = self.choices.first()?;
+        choice.message.clone().or_else(|| choice.delta.clone())
     }
 }
 
@@ -86,6 +301,7 @@ impl Message {
         Message {
             role: Some(role),
             content: Some(content),
+            ..Default::default()
         }
     }
 
@@ -97,58 +313,454 @@ impl Message {
         Message::new("assistant".to_string(), content)
     }
 
+    pub fn system(content: String) -> Self {
+        Message::new("system".to_string(), content)
+    }
+
+    /// a failed request shown in the transcript, never sent to a backend
+    pub fn error(content: String) -> Self {
+        Message::new("error".to_string(), content)
+    }
+
+    /// the result of running tool call `call`
+    pub fn tool(call: &ToolCall, content: String) -> Self {
+        Message {
+            tool_call_id: call.id.clone(),
+            name: call.function.name.clone(),
+            ..Message::new("tool".to_string(), content)
+        }
+    }
+
+    pub fn is_assistant(&self) -> bool {
+        self.role.as_deref() == Some("assistant")
+    }
+
+    pub fn is_system(&self) -> bool {
+        self.role.as_deref() == Some("system")
+    }
+
+    pub fn is_error(&self) -> bool {
+        self.role.as_deref() == Some("error")
+    }
+
+    pub fn is_user(&self) -> bool {
+        self.role.as_deref() == Some("user")
+    }
+
+    pub fn is_tool(&self) -> bool {
+        self.role.as_deref() == Some("tool")
+    }
+
+    /// an answer asking for tools rather than giving one
+    pub fn has_tool_calls(&self) -> bool {
+        self.tool_calls
+            .as_ref()
+            .is_some_and(|calls| !calls.is_empty())
+    }
+
+    /// answers and errors take the place of an answer in the transcript
+    pub fn is_answer(&self) -> bool {
+        self.is_assistant() || self.is_error()
+    }
+
+    /// the content laid out for `max_width` columns, answers are rendered
+    /// as markdown while prompts are shown as typed
+    pub fn to_lines(&self, max_width: u16) -> Vec<Line<'static>> {
+        let content = self.content.as_deref().unwrap_or("");
+        if self.is_system() {
+            // collapsed to its first line, it rarely changes and can be long
+            let first = content.lines().next().unwrap_or_default();
+            let mut lines = markdown::render_plain(first, max_width.saturating_sub(1));
+            if lines.len() > 1 || content.trim_end().contains('\n') {
+                lines.truncate(1);
+                lines[0].push_span("…");
+            }
+            return lines;
+        }
+        if self.is_tool() {
+            // tool output is mostly for the model, the start is enough
+            let mut lines = markdown::render_plain(content.trim_end(), max_width);
+            if lines.len() > TOOL_LINES {
+                let more = lines.len() - TOOL_LINES + 1;
+                lines.truncate(TOOL_LINES - 1);
+                lines.push(Line::from(format!("… {} more lines", more)).dark_gray());
+            }
+            return lines;
+        }
+        if !self.is_assistant() {
+            return markdown::render_plain(content, max_width);
+        }
+
+        let mut lines = if content.is_empty() {
+            Vec::new()
+        } else {
+            markdown::render(content, max_width)
+        };
+        for call in self.tool_calls.iter().flatten() {
+            let name = call.function.name.as_deref().unwrap_or("?");
+            let call = format!("→ {}({})", name, call.function.arguments);
+            let call = markdown::render_plain(&call, max_width);
+            lines.extend(call.into_iter().map(|line| line.yellow()));
+        }
+        lines
+    }
+
     pub fn len_by_columns(&self, max_width: u16) -> usize {
-        self.content
-            .as_deref()
-            .unwrap()
-            .split('\n')
-            .fold(0, |acc, ln| {
-                let len = ln.chars().count();
-                let count = len / max_width as usize + 1;
-                acc + count
-            })
+        self.to_lines(max_width).len()
     }
-}
 
-impl Widget for &Message {
-    fn render(self, area: Rect, buf: &mut Buffer) {
+    /// draw the message in its block with the given content `lines`
+    pub fn render_lines(&self, lines: Vec<Line<'static>>, area: Rect, buf: &mut Buffer) {
         if self.content.is_none() {
             return;
         }
 
         let (align, title_color) = match self.role.as_deref() {
             Some("user") => (Alignment::Right, Color::Blue),
+            Some("error") => (Alignment::Left, Color::Red),
+            Some("system") => (Alignment::Left, Color::Magenta),
+            Some("tool") => (Alignment::Left, Color::Yellow),
             _ => (Alignment::Left, Color::Green),
         };
 
-        let block = Block::default()
-            .title_top(self.role.as_ref().unwrap().as_str())
+        // the profile of an answer, the tool of a tool result
+        let mut title = match self.provider.as_ref().or(self.name.as_ref()) {
+            Some(label) => format!("{} · {}", self.role.as_deref().unwrap(), label),
+            None => self.role.clone().unwrap(),
+        };
+        if self.interrupted {
+            title.push_str(" [interrupted]");
+        }
+        if let Some((pos, count)) = self.branch {
+            title = format!("‹ {}/{} › {}", pos + 1, count, title);
+        }
+        let mut block = Block::default()
+            .title_top(title)
             .title_style(title_color)
             .title_alignment(align)
             .borders(Borders::ALL);
+        if let Some(ref stats) = self.stats {
+            // drop the less important parts in narrow blocks
+            let mut parts = stats.parts();
+            while parts.join(" · ").width() + 2 > area.width as usize {
+                parts.pop();
+            }
+            block = block.title_bottom(Line::from(parts.join(" · ")).dark_gray().right_aligned());
+        }
+        let block = if self.is_error() {
+            block.border_style(Color::Red)
+        } else {
+            block
+        };
 
-        let text = Text::from(self.content.as_deref().unwrap());
-        Paragraph::new(Text::from(text))
+        Paragraph::new(Text::from(lines))
             .block(block)
-            .wrap(Wrap { trim: false })
             .render(area, buf);
     }
 }
 
+impl Widget for &Message {
+    fn render(self, area: Rect, buf: &mut Buffer) {
+        let lines = self.to_lines(area.width.saturating_sub(2));
+        self.render_lines(lines, area, buf);
+    }
+}
+
+/// `Message::to_lines` of the messages on screen, markdown is too slow to
+/// render again every frame. keyed by what a message shows and the width,
+/// so edits and resizes miss on their own.
+#[derive(Default)]
+pub struct LineCache {
+    current: HashMap<u64, Vec<Line<'static>>>,
+    previous: HashMap<u64, Vec<Line<'static>>>,
+}
+
+impl LineCache {
+    /// start a new frame, lines not asked for during the last one are dropped
+    pub fn frame(&mut self) {
+        self.previous = std::mem::take(&mut self.current);
+    }
+
+    pub fn get(&mut self, msg: &Message, max_width: u16) -> &[Line<'static>] {
+        let mut hasher = DefaultHasher::new();
+        (&msg.role, &msg.content, max_width).hash(&mut hasher);
+        for call in msg.tool_calls.iter().flatten() {
+            (&call.function.name, &call.function.arguments).hash(&mut hasher);
+        }
+        let key = hasher.finish();
+        let lines = match self.previous.remove(&key) {
+            Some(lines) => lines,
+            None if self.current.contains_key(&key) => return &self.current[&key],
+            None => msg.to_lines(max_width),
+        };
+        self.current.entry(key).or_insert(lines)
+    }
+}
+
 #[async_trait]
 pub trait LLMService: Send + Sync {
+    /// stream the answer to `history`, which ends with the prompt or the
+    /// results of the tools the model asked for. backends without tool
+    /// support ignore `tools`.
     async fn request(
         &mut self,
-        prompt: &str,
-        mut history: Vec<Message>,
+        id: StreamId,
+        history: Vec<Message>,
+        params: GenerationParams,
+        tools: Vec<ToolSpec>,
         tx: UnboundedSender<Event>,
-    ) -> Result<()>;
+        cancel: CancellationToken,
+    ) -> Result<(), LLMError>;
+}
+
+/// http client shared by the backends, a stalled stream times out instead
+/// of hanging forever
+pub(crate) fn http_client() -> Client {
+    Client::builder()
+        .connect_timeout(Duration::from_secs(10))
+        .read_timeout(Duration::from_secs(120))
+        .build()
+        .unwrap_or_default()
+}
+
+/// send the request built by `req` until it gets a 2xx response, retrying
+/// transient failures as `policy` allows. each retry is announced with
+/// `LLMEventRetry`, `None` means it was cancelled while waiting.
+pub(crate) async fn send_with_retry(
+    id: StreamId,
+    policy: &RetryPolicy,
+    req: impl Fn() -> RequestBuilder,
+    tx: &UnboundedSender<Event>,
+    cancel: &CancellationToken,
+) -> Result<Option<Response>, LLMError> {
+    let mut attempt = 0;
+    loop {
+        let resp = select! {
+            _ = cancel.cancelled() => return Ok(None),
+            resp = req().send() => resp,
+        };
+        let e = match resp {
+            Ok(resp) if resp.status().is_success() => return Ok(Some(resp)),
+            Ok(resp) => LLMError::from_response(resp).await,
+            Err(e) => e.into(),
+        };
+        if !e.is_transient() || attempt >= policy.max_retries {
+            return Err(e);
+        }
+
+        // a server asking for a longer wait than the policy allows is
+        // better reported than retried too early or waited on for ages
+        let delay = match e {
+            LLMError::RateLimit {
+                retry_after: Some(after),
+                ..
+            } if after > Duration::from_millis(policy.max_delay_ms) => return Err(e),
+            LLMError::RateLimit {
+                retry_after: Some(after),
+                ..
+            } => after,
+            _ => policy.delay(attempt),
+        };
+        attempt += 1;
+        let status = format!(
+            "{}, retrying in {}s ({}/{})",
+            e.brief(),
+            delay.as_secs_f32().ceil(),
+            attempt,
+            policy.max_retries
+        );
+        tx.send(Event::LLMEventRetry(id, status)).unwrap();
+
+        select! {
+            _ = cancel.cancelled() => return Ok(None),
+            _ = tokio::time::sleep(delay) => {}
+        }
+    }
+}
+
+/// what a backend learned from the events of an answer so far
+pub(crate) struct Reply {
+    pub timer: Timer,
+    pub usage: Option<Usage>,
+    pub stop_reason: Option<String>,
+}
+
+/// whether more events of an answer are to be read
+pub(crate) enum Flow {
+    Continue,
+    Done,
+}
+
+/// read the `events` of an answer, each handed to `handle` along with the
+/// `Reply` so far, until they run out, `handle` is done or fails, or
+/// `cancel` fires. ends with `LLMEventStats` and `LLMEventEnd` or with
+/// `LLMEventCancelled` unless an error is returned.
+pub(crate) async fn read_events<S, T, F>(
+    id: StreamId,
+    events: S,
+    timer: Timer,
+    tx: &UnboundedSender<Event>,
+    cancel: &CancellationToken,
+    mut handle: F,
+) -> Result<(), LLMError>
+where
+    S: Stream<Item = Result<T, LLMError>>,
+    F: FnMut(T, &mut Reply) -> Result<Flow, LLMError>,
+{
+    pin_mut!(events);
+    let mut reply = Reply {
+        timer,
+        usage: None,
+        stop_reason: None,
+    };
+    loop {
+        // dropping the body on cancellation closes the http stream
+        let event = select! {
+            _ = cancel.cancelled() => {
+                tx.send(Event::LLMEventCancelled(id)).unwrap();
+                return Ok(());
+            }
+            event = events.next() => event,
+        };
+        let Some(event) = event else {
+            break;
+        };
+        if let Flow::Done = handle(event?, &mut reply)? {
+            break;
+        }
+    }
+
+    let mut stats = reply.timer.stats(reply.usage.as_ref());
+    stats.stop_reason = reply.stop_reason;
+    tx.send(Event::LLMEventStats(id, stats)).unwrap();
+    tx.send(Event::LLMEventEnd(id)).unwrap();
+    Ok(())
 }
 
-pub struct LLMProvider {}
+pub type SharedLLMService = Arc<Mutex<Box<dyn LLMService + 'static>>>;
+
+/// registry of the configured profiles and their backends
+pub struct LLMProvider {
+    profiles: Vec<Profile>,
+    services: Vec<SharedLLMService>,
+    active: usize,
+    /// profiles a prompt is fanned out to, parallel mode needs at least two
+    parallel: Vec<usize>,
+    /// generation parameters of profiles that don't set their own
+    defaults: GenerationParams,
+}
 
 impl LLMProvider {
-    pub fn new() -> Box<dyn LLMService> {
-        Box::new(ChatGPT::new())
+    pub fn new(config: &Config) -> Self {
+        let profiles = config.profiles.values().cloned().collect::<Vec<_>>();
+        let services = profiles
+            .iter()
+            .map(|profile| Arc::new(Mutex::new(Self::build(profile))))
+            .collect();
+        let active = config
+            .default
+            .as_ref()
+            .and_then(|name| profiles.iter().position(|p| &p.name == name))
+            .unwrap_or(0);
+        let mut parallel = config
+            .parallel
+            .iter()
+            .filter_map(|name| profiles.iter().position(|p| &p.name == name))
+            .collect::<Vec<_>>();
+        parallel.sort();
+        parallel.dedup();
+
+        Self {
+            profiles,
+            services,
+            active,
+            parallel,
+            defaults: config.params.clone(),
+        }
+    }
+
+    /// generation parameters for profile `i`: the config defaults, the
+    /// profile's own and then `overrides`
+    pub fn params(&self, i: usize, overrides: &GenerationParams) -> GenerationParams {
+        self.defaults
+            .merge(&self.profiles[i].params)
+            .merge(overrides)
+    }
+
+    pub fn build(profile: &Profile) -> Box<dyn LLMService> {
+        match profile.provider {
+            ProviderKind::OpenAI => Box::new(ChatGPT::new(profile.clone())),
+            ProviderKind::Ollama => Box::new(Ollama::new(profile.clone())),
+            ProviderKind::Anthropic => Box::new(Anthropic::new(profile.clone())),
+            ProviderKind::Gemini => Box::new(Gemini::new(profile.clone())),
+        }
+    }
+
+    pub fn profiles(&self) -> &[Profile] {
+        &self.profiles
+    }
+
+    pub fn active_index(&self) -> usize {
+        self.active
+    }
+
+    pub fn active_profile(&self) -> &Profile {
+        &self.profiles[self.active]
+    }
+
+    pub fn active_service(&self) -> SharedLLMService {
+        self.service(self.active)
+    }
+
+    pub fn service(&self, index: usize) -> SharedLLMService {
+        Arc::clone(&self.services[index])
+    }
+
+    pub fn is_parallel(&self) -> bool {
+        self.parallel.len() > 1
+    }
+
+    pub fn in_parallel(&self, index: usize) -> bool {
+        self.parallel.contains(&index)
+    }
+
+    /// add or remove the profile at `index` from the parallel set
+    pub fn toggle_parallel(&mut self, index: usize) {
+        match self.parallel.iter().position(|i| *i == index) {
+            Some(pos) => {
+                self.parallel.remove(pos);
+            }
+            None if index < self.profiles.len() => {
+                self.parallel.push(index);
+                self.parallel.sort();
+            }
+            None => {}
+        }
+    }
+
+    /// indices of the profiles the next prompt is sent to
+    pub fn targets(&self) -> Vec<usize> {
+        if self.is_parallel() {
+            self.parallel.clone()
+        } else {
+            vec![self.active]
+        }
+    }
+
+    /// make the profile at `index` active, returns false if out of range
+    pub fn switch(&mut self, index: usize) -> bool {
+        if index < self.profiles.len() {
+            self.active = index;
+            true
+        } else {
+            false
+        }
+    }
+
+    pub fn switch_by_name(&mut self, name: &str) -> bool {
+        match self.profiles.iter().position(|p| p.name == name) {
+            Some(index) => self.switch(index),
+            None => false,
+        }
     }
 }
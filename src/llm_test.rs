@@ -1,6 +1,133 @@
 #[cfg(test)]
 mod tests {
+    use crate::anthropic::{self, Anthropic};
+    use crate::chatgpt::{read_stream, ChatGPT};
+    use crate::config::{Config, GenerationParams, Profile, ProviderKind, RetryPolicy};
+    use crate::error::LLMError;
+    use crate::event::Event;
+    use crate::gemini::Gemini;
     use crate::llm::*;
+    use crate::ollama::Ollama;
+    use tokio::{
+        io::{AsyncReadExt, AsyncWriteExt},
+        net::{TcpListener, TcpStream},
+        sync::mpsc::{unbounded_channel, UnboundedReceiver},
+    };
+    use tokio_util::sync::CancellationToken;
+
+    /// serve a single http request on localhost, replying with `body` written
+    /// in `chunk_size` pieces so the client sees arbitrary chunk boundaries.
+    /// with `hang` the connection stays open afterwards like a stalled stream.
+    async fn mock_server(
+        content_type: &'static str,
+        body: &'static str,
+        chunk_size: usize,
+        hang: bool,
+    ) -> String {
+        let headers = format!("Content-Type: {}\r\n", content_type);
+        mock_http(200, headers, body, chunk_size, hang).await
+    }
+
+    /// like `mock_server` with any status and raw header lines
+    async fn mock_http(
+        status: u16,
+        headers: String,
+        body: &'static str,
+        chunk_size: usize,
+        hang: bool,
+    ) -> String {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        tokio::spawn(async move {
+            let (sock, _) = listener.accept().await.unwrap();
+            respond(sock, status, &headers, body, chunk_size, hang).await;
+        });
+        format!("http://{}", addr)
+    }
+
+    /// answer one connection after another with the given replies
+    async fn mock_replies(replies: Vec<(u16, &'static str, &'static str)>) -> String {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        tokio::spawn(async move {
+            for (status, headers, body) in replies {
+                let (sock, _) = listener.accept().await.unwrap();
+                respond(sock, status, headers, body, 1024, false).await;
+            }
+        });
+        format!("http://{}", addr)
+    }
+
+    async fn respond(
+        mut sock: TcpStream,
+        status: u16,
+        headers: &str,
+        body: &str,
+        chunk_size: usize,
+        hang: bool,
+    ) {
+        let mut req = Vec::new();
+        let mut buf = [0u8; 4096];
+        loop {
+            let n = sock.read(&mut buf).await.unwrap();
+            req.extend_from_slice(&buf[..n]);
+            let text = String::from_utf8_lossy(&req);
+            if let Some(pos) = text.find("\r\n\r\n") {
+                let len = text
+                    .lines()
+                    .find_map(|ln| {
+                        ln.to_lowercase()
+                            .strip_prefix("content-length:")
+                            .map(|v| v.trim().parse::<usize>().unwrap())
+                    })
+                    .unwrap_or(0);
+                if req.len() >= pos + 4 + len {
+                    break;
+                }
+            }
+            if n == 0 {
+                break;
+            }
+        }
+
+        let head = format!(
+            "HTTP/1.1 {} Mock\r\n{}Connection: close\r\n\r\n",
+            status, headers
+        );
+        sock.write_all(head.as_bytes()).await.unwrap();
+        for chunk in body.as_bytes().chunks(chunk_size) {
+            sock.write_all(chunk).await.unwrap();
+            sock.flush().await.unwrap();
+        }
+        if hang {
+            tokio::time::sleep(std::time::Duration::from_secs(60)).await;
+        }
+        sock.shutdown().await.unwrap();
+    }
+
+    const OPENAI_SSE: &str = include_str!("../tests/fixtures/openai_chat.sse");
+    const OPENAI_ANSWER: &str = "天空是蓝色的 — mostly 🌤.";
+
+    fn collect_answer(rx: &mut UnboundedReceiver<Event>) -> (String, Option<Event>) {
+        let mut answer = String::new();
+        let mut last = None;
+        while let Ok(ev) = rx.try_recv() {
+            if let Event::LLMEventDelta(_, msg) = &ev {
+                answer.push_str(msg.content.as_deref().unwrap_or_default());
+            }
+            last = Some(ev);
+        }
+        (answer, last)
+    }
+
+    /// run `read_stream` over the given body chunks
+    async fn read_chunks(chunks: Vec<&[u8]>) -> (Result<(), LLMError>, String, Option<Event>) {
+        let (tx, mut rx) = unbounded_channel();
+        let body = futures::stream::iter(chunks.into_iter().map(Ok::<_, LLMError>));
+        let res = read_stream(1, body, Timer::start(), &tx, &CancellationToken::new()).await;
+        let (answer, last) = collect_answer(&mut rx);
+        (res, answer, last)
+    }
 
     fn split_str_by_40_chars(input: &str, n: usize) -> Vec<String> {
         input
@@ -33,14 +160,897 @@ mod tests {
         assert!(msg.is_ok());
     }
 
+    #[test]
+    fn llm_resolve_no_message() {
+        let payload = r#"{"id":"1","object":"chat.completion.chunk","created":1,"model":"m","choices":[{"index":0,"finish_reason":"stop"}]}"#;
+        let data = serde_json::from_str::<LLMResponse>(payload).unwrap();
+        assert!(data.extract_message().is_none());
+        assert_eq!(data.finish_reason(), Some("stop"));
+    }
+
+    #[tokio::test]
+    async fn llm_request_panic() {
+        let task = tokio::spawn(async { panic!("boom") });
+        let e = LLMError::from(task.await.unwrap_err());
+        assert_eq!(e.to_string(), "request crashed: boom");
+    }
+
     #[test]
     fn llm_test_len_by_columns() {
         let msg = Message {
             role: Some("assistant".to_string()),
             content: Some("Hello! How can I help you today? If you have any questions about a particular topic or just want to chat, I'm here to assist. Let me know what's on your mind.".to_string()),
+            ..Default::default()
         };
 
         assert_eq!(msg.len_by_columns(80), 2);
         assert_eq!(msg.len_by_columns(40), 4);
+
+        let mut cache = LineCache::default();
+        assert_eq!(cache.get(&msg, 80).len(), 2);
+        assert_eq!(cache.get(&msg, 40).len(), 4);
+        // an edit is a different message
+        let mut edited = msg.clone();
+        edited.content = Some("Hello!".to_owned());
+        assert_eq!(cache.get(&edited, 80).len(), 1);
+        cache.frame();
+        assert_eq!(cache.get(&msg, 80).len(), 2);
+    }
+
+    #[tokio::test]
+    async fn ollama_stream_ndjson() {
+        let body = include_str!("../tests/fixtures/ollama_chat.ndjson");
+        let url = mock_server("application/x-ndjson", body, 7, false).await;
+
+        let mut ollama = Ollama::new(Profile {
+            provider: ProviderKind::Ollama,
+            endpoint: Some(format!("{}/api/chat", url)),
+            model: "llama3.1".to_string(),
+            ..Default::default()
+        });
+        let (tx, mut rx) = unbounded_channel();
+        ollama
+            .request(
+                1,
+                vec![Message::user("why is the sky blue?".to_owned())],
+                Default::default(),
+                vec![],
+                tx,
+                CancellationToken::new(),
+            )
+            .await
+            .unwrap();
+
+        let mut events = vec![];
+        while let Some(ev) = rx.recv().await {
+            events.push(ev);
+        }
+
+        assert!(matches!(events.first(), Some(Event::LLMEventStart(1))));
+        assert!(matches!(events.last(), Some(Event::LLMEventEnd(1))));
+        let Some(Event::LLMEventStats(1, stats)) = &events.get(events.len() - 2) else {
+            panic!("no stats before the end: {:?}", events);
+        };
+        assert_eq!(stats.prompt_tokens, Some(14));
+        assert_eq!(stats.completion_tokens, Some(7));
+        assert_eq!(stats.generation, Some(0.124578));
+        assert!(stats.ttft.is_some());
+        let answer = events
+            .iter()
+            .filter_map(|ev| match ev {
+                Event::LLMEventDelta(1, msg) => msg.content.clone(),
+                _ => None,
+            })
+            .collect::<String>();
+        assert_eq!(answer, "The sky is blue — mostly.");
+    }
+
+    #[test]
+    fn config_parse_profiles() {
+        let config = Config::parse(
+            r#"
+            default = "local"
+
+            [profiles.groq]
+            endpoint = "https://api.groq.com/openai/v1/chat/completions"
+            api_key = "secret"
+            model = "llama-3.1-70b-versatile"
+
+            [profiles.local]
+            provider = "ollama"
+            model = "llama3.1"
+            temperature = 0.2
+            "#,
+        )
+        .unwrap();
+
+        let provider = LLMProvider::new(&config);
+        assert_eq!(provider.profiles().len(), 2);
+        assert_eq!(provider.active_profile().name, "local");
+        assert_eq!(
+            provider.active_profile().endpoint(),
+            "http://localhost:11434/api/chat"
+        );
+
+        let groq = &config.profiles["groq"];
+        assert_eq!(groq.provider, ProviderKind::OpenAI);
+        assert_eq!(groq.api_key(), "secret");
+    }
+
+    #[test]
+    fn config_personas() {
+        let mut config = Config::parse(
+            r#"
+            persona = "reviewer"
+
+            [personas.reviewer]
+            description = "terse code review"
+            prompt = "You review code."
+            "#,
+        )
+        .unwrap();
+
+        let dir = std::env::temp_dir().join(format!("llmi-personas-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        std::fs::write(dir.join("pirate.md"), "Talk like a pirate.\n").unwrap();
+        std::fs::write(dir.join("reviewer.md"), "overridden by the config").unwrap();
+        std::fs::write(dir.join("notes.json"), "{}").unwrap();
+        config.load_personas(&dir).unwrap();
+        std::fs::remove_dir_all(dir).unwrap();
+
+        assert_eq!(config.persona.as_deref(), Some("reviewer"));
+        assert_eq!(
+            config.personas.keys().collect::<Vec<_>>(),
+            vec!["pirate", "reviewer"]
+        );
+        assert_eq!(config.personas["pirate"].prompt, "Talk like a pirate.");
+        assert_eq!(config.personas["reviewer"].prompt, "You review code.");
+        assert_eq!(config.personas["reviewer"].name, "reviewer");
+    }
+
+    #[test]
+    fn generation_params() {
+        let config = Config::parse(
+            r#"
+            [params]
+            temperature = 0.7
+            max_tokens = 1000
+
+            [profiles.groq]
+            model = "llama-3.1-70b-versatile"
+            max_tokens = 3000
+            stop = ["END"]
+
+            [profiles.local]
+            provider = "ollama"
+            model = "llama3.1"
+            "#,
+        )
+        .unwrap();
+        let provider = LLMProvider::new(&config);
+
+        let mut overrides = GenerationParams::default();
+        overrides.set("temperature", "0.2").unwrap();
+        overrides.set("response_format", "json").unwrap();
+        assert!(overrides.set("top_p", "1.5").is_err());
+        assert!(overrides.set("temprature", "1").is_err());
+
+        let params = provider.params(0, &overrides);
+        assert_eq!(
+            params.describe(),
+            vec![
+                "max_tokens=3000",
+                "temperature=0.2",
+                "stop=\"END\"",
+                "response_format=json"
+            ]
+        );
+        let body = ChatGPT::new(config.profiles["groq"].clone()).body(&[], &params, &[]);
+        assert_eq!(body["max_tokens"], 3000);
+        assert_eq!(body["stop"], serde_json::json!(["END"]));
+        assert_eq!(body["response_format"]["type"], "json_object");
+        assert!(body.get("seed").is_none());
+
+        overrides.unset("temperature").unwrap();
+        let params = provider.params(1, &overrides);
+        let body = Ollama::new(config.profiles["local"].clone()).body(&[], &params);
+        assert_eq!(body["options"]["num_predict"], 1000);
+        assert_eq!(body["options"]["temperature"].as_f64(), Some(0.7f32 as f64));
+        assert_eq!(body["format"], "json");
+    }
+
+    #[tokio::test]
+    async fn ollama_cancel_stalled_stream() {
+        let body =
+            "{\"message\":{\"role\":\"assistant\",\"content\":\"partial\"},\"done\":false}\n";
+        let url = mock_server("application/x-ndjson", body, 1024, true).await;
+
+        let mut ollama = Ollama::new(Profile {
+            provider: ProviderKind::Ollama,
+            endpoint: Some(url),
+            model: "llama3.1".to_string(),
+            ..Default::default()
+        });
+        let (tx, mut rx) = unbounded_channel();
+        let cancel = CancellationToken::new();
+        let task = tokio::spawn({
+            let cancel = cancel.clone();
+            async move {
+                ollama
+                    .request(
+                        7,
+                        vec![Message::user("hi".to_owned())],
+                        Default::default(),
+                        vec![],
+                        tx,
+                        cancel,
+                    )
+                    .await
+            }
+        });
+
+        assert!(matches!(rx.recv().await, Some(Event::LLMEventStart(7))));
+        assert!(matches!(rx.recv().await, Some(Event::LLMEventDelta(7, _))));
+        cancel.cancel();
+        assert!(matches!(rx.recv().await, Some(Event::LLMEventCancelled(7))));
+        task.await.unwrap().unwrap();
+    }
+
+    #[tokio::test]
+    async fn chatgpt_http_errors() {
+        let body = r#"{"error":{"message":"Invalid API Key","type":"invalid_request_error","code":"invalid_api_key"}}"#;
+        let headers = "Content-Type: application/json\r\n".to_string();
+        let url = mock_http(401, headers, body, 1024, false).await;
+
+        let mut chatgpt = ChatGPT::new(Profile {
+            endpoint: Some(url),
+            model: "llama-3.1-70b-versatile".to_string(),
+            ..Default::default()
+        });
+        let (tx, _rx) = unbounded_channel();
+        let e = chatgpt
+            .request(
+                1,
+                vec![Message::user("hi".to_owned())],
+                Default::default(),
+                vec![],
+                tx,
+                CancellationToken::new(),
+            )
+            .await
+            .unwrap_err();
+        assert_eq!(e.code(), Some(401));
+        assert_eq!(
+            e.to_string(),
+            "HTTP 401 authentication failed: Invalid API Key"
+        );
+
+        let headers = "Content-Type: application/json\r\nRetry-After: 7\r\n".to_string();
+        let url = mock_http(429, headers, "{}", 1024, false).await;
+        let mut chatgpt = ChatGPT::new(Profile {
+            endpoint: Some(url),
+            retry: RetryPolicy {
+                max_retries: 0,
+                ..Default::default()
+            },
+            ..Default::default()
+        });
+        let (tx, _rx) = unbounded_channel();
+        let e = chatgpt
+            .request(
+                1,
+                vec![Message::user("hi".to_owned())],
+                Default::default(),
+                vec![],
+                tx,
+                CancellationToken::new(),
+            )
+            .await
+            .unwrap_err();
+        assert!(matches!(
+            e,
+            LLMError::RateLimit { retry_after: Some(d), .. } if d.as_secs() == 7
+        ));
+    }
+
+    #[tokio::test]
+    async fn sse_split_at_every_offset() {
+        let body = OPENAI_SSE.as_bytes();
+        for at in 0..=body.len() {
+            let (res, answer, last) = read_chunks(vec![&body[..at], &body[at..]]).await;
+            assert!(res.is_ok(), "split at {}: {:?}", at, res);
+            assert_eq!(answer, OPENAI_ANSWER, "split at {}", at);
+            assert!(matches!(last, Some(Event::LLMEventEnd(1))));
+        }
+
+        // every byte on its own
+        let (res, answer, _) = read_chunks(body.chunks(1).collect()).await;
+        assert!(res.is_ok());
+        assert_eq!(answer, OPENAI_ANSWER);
+    }
+
+    #[tokio::test]
+    async fn sse_error_event() {
+        let body = "data: {\"error\":{\"message\":\"model is overloaded\"}}\n\n";
+        let (res, _, last) = read_chunks(vec![body.as_bytes()]).await;
+        assert_eq!(res, Err(LLMError::Api("model is overloaded".to_string())));
+        assert!(last.is_none());
+    }
+
+    #[tokio::test]
+    async fn stream_usage() {
+        // groq puts it in `x_groq` of the last chunk with choices
+        let (tx, mut rx) = unbounded_channel();
+        let body = futures::stream::iter([Ok::<_, LLMError>(OPENAI_SSE.as_bytes())]);
+        read_stream(1, body, Timer::start(), &tx, &CancellationToken::new())
+            .await
+            .unwrap();
+        let stats = std::iter::from_fn(|| rx.try_recv().ok())
+            .find_map(|ev| match ev {
+                Event::LLMEventStats(1, stats) => Some(stats),
+                _ => None,
+            })
+            .unwrap();
+        assert_eq!(stats.prompt_tokens, Some(12));
+        assert_eq!(stats.completion_tokens, Some(9));
+        assert_eq!(stats.tokens_per_sec(), Some(450.0));
+
+        // openai sends it with `include_usage` in a chunk without choices,
+        // other servers without the ids and between keep-alives
+        let body = concat!(
+            "data: {\"id\":\"1\",\"object\":\"chat.completion.chunk\",\"created\":1,\"model\":\"gpt-4o\",\"choices\":[{\"index\":0,\"delta\":{\"content\":\"hi\"}}]}\n\n",
+            "data: {\"type\":\"ping\",\"choices\":null}\n\n",
+            "data: {\"usage\":{\"prompt_tokens\":8,\"completion_tokens\":1,\"total_tokens\":9}}\n\n",
+            "data: [DONE]\n\n",
+        );
+        let (tx, mut rx) = unbounded_channel();
+        let body = futures::stream::iter([Ok::<_, LLMError>(body.as_bytes())]);
+        read_stream(1, body, Timer::start(), &tx, &CancellationToken::new())
+            .await
+            .unwrap();
+        let mut stats = std::iter::from_fn(|| rx.try_recv().ok())
+            .find_map(|ev| match ev {
+                Event::LLMEventStats(1, stats) => Some(stats),
+                _ => None,
+            })
+            .unwrap();
+        assert_eq!(stats.prompt_tokens, Some(8));
+        assert_eq!(stats.completion_tokens, Some(1));
+        (stats.ttft, stats.generation) = (None, None);
+        assert_eq!(stats.describe(), "8+1 tok");
+
+        let data = ChatGPT::new(Profile::default()).body(&[], &Default::default(), &[]);
+        assert_eq!(data["stream_options"]["include_usage"], true);
+    }
+
+    #[tokio::test]
+    async fn chatgpt_stream_sse() {
+        let url = mock_server("text/event-stream", OPENAI_SSE, 5, false).await;
+        let mut chatgpt = ChatGPT::new(Profile {
+            endpoint: Some(url),
+            model: "llama-3.1-70b-versatile".to_string(),
+            ..Default::default()
+        });
+        let (tx, mut rx) = unbounded_channel();
+        chatgpt
+            .request(
+                1,
+                vec![Message::user("why is the sky blue?".to_owned())],
+                Default::default(),
+                vec![],
+                tx,
+                CancellationToken::new(),
+            )
+            .await
+            .unwrap();
+
+        assert!(matches!(rx.recv().await, Some(Event::LLMEventStart(1))));
+        let (answer, last) = collect_answer(&mut rx);
+        assert_eq!(answer, OPENAI_ANSWER);
+        assert!(matches!(last, Some(Event::LLMEventEnd(1))));
+    }
+
+    #[tokio::test]
+    async fn chatgpt_retry_transient() {
+        let url = mock_replies(vec![
+            (503, "", "upstream unavailable"),
+            (429, "Retry-After: 0\r\n", "{}"),
+            (200, "Content-Type: text/event-stream\r\n", OPENAI_SSE),
+        ])
+        .await;
+        let retry = RetryPolicy {
+            max_retries: 2,
+            initial_delay_ms: 10,
+            max_delay_ms: 10,
+        };
+        let mut chatgpt = ChatGPT::new(Profile {
+            endpoint: Some(url),
+            retry,
+            ..Default::default()
+        });
+        let (tx, mut rx) = unbounded_channel();
+        chatgpt
+            .request(
+                1,
+                vec![Message::user("hi".to_owned())],
+                Default::default(),
+                vec![],
+                tx,
+                CancellationToken::new(),
+            )
+            .await
+            .unwrap();
+
+        assert!(matches!(rx.recv().await, Some(Event::LLMEventStart(1))));
+        match rx.recv().await {
+            Some(Event::LLMEventRetry(1, status)) => {
+                assert_eq!(status, "HTTP 503, retrying in 1s (1/2)")
+            }
+            ev => panic!("unexpected {:?}", ev),
+        }
+        match rx.recv().await {
+            Some(Event::LLMEventRetry(1, status)) => {
+                assert_eq!(status, "rate limited, retrying in 0s (2/2)")
+            }
+            ev => panic!("unexpected {:?}", ev),
+        }
+        let (answer, last) = collect_answer(&mut rx);
+        assert_eq!(answer, OPENAI_ANSWER);
+        assert!(matches!(last, Some(Event::LLMEventEnd(1))));
+
+        // out of retries, the last error is returned
+        let url = mock_replies(vec![(502, "", "bad gateway"), (502, "", "bad gateway")]).await;
+        let mut chatgpt = ChatGPT::new(Profile {
+            endpoint: Some(url),
+            retry: RetryPolicy {
+                max_retries: 1,
+                ..retry
+            },
+            ..Default::default()
+        });
+        let (tx, _rx) = unbounded_channel();
+        let e = chatgpt
+            .request(
+                1,
+                vec![Message::user("hi".to_owned())],
+                Default::default(),
+                vec![],
+                tx,
+                CancellationToken::new(),
+            )
+            .await
+            .unwrap_err();
+        assert_eq!(e.to_string(), "HTTP 502: bad gateway");
+
+        // a wait longer than `max_delay_ms` is not sat out
+        let url = mock_replies(vec![(429, "Retry-After: 3600\r\n", "{}")]).await;
+        let mut chatgpt = ChatGPT::new(Profile {
+            endpoint: Some(url),
+            retry,
+            ..Default::default()
+        });
+        let (tx, mut rx) = unbounded_channel();
+        let e = chatgpt
+            .request(
+                1,
+                vec![Message::user("hi".to_owned())],
+                Default::default(),
+                vec![],
+                tx,
+                CancellationToken::new(),
+            )
+            .await
+            .unwrap_err();
+        assert_eq!(
+            e.to_string(),
+            "HTTP 429 rate limited: {} (retry after 3600s)"
+        );
+        assert!(matches!(rx.try_recv(), Ok(Event::LLMEventStart(1))));
+        assert!(rx.try_recv().is_err());
+    }
+
+    const ANTHROPIC_SSE: &str = include_str!("../tests/fixtures/anthropic_messages.sse");
+
+    /// run `anthropic::read_stream` over the given body chunks
+    async fn read_anthropic(chunks: Vec<&[u8]>) -> (Result<(), LLMError>, Vec<Event>) {
+        let (tx, mut rx) = unbounded_channel();
+        let body = futures::stream::iter(chunks.into_iter().map(Ok::<_, LLMError>));
+        let res =
+            anthropic::read_stream(1, body, Timer::start(), &tx, &CancellationToken::new()).await;
+        (res, std::iter::from_fn(|| rx.try_recv().ok()).collect())
+    }
+
+    fn answer_of(events: &[Event]) -> String {
+        events
+            .iter()
+            .filter_map(|ev| match ev {
+                Event::LLMEventDelta(1, msg) => msg.content.clone(),
+                _ => None,
+            })
+            .collect()
+    }
+
+    #[tokio::test]
+    async fn anthropic_stream_sse() {
+        let url = mock_server("text/event-stream", ANTHROPIC_SSE, 5, false).await;
+        let mut anthropic = Anthropic::new(Profile {
+            provider: ProviderKind::Anthropic,
+            endpoint: Some(url),
+            model: "claude-3-5-sonnet-20240620".to_string(),
+            ..Default::default()
+        });
+        let (tx, mut rx) = unbounded_channel();
+        anthropic
+            .request(
+                1,
+                vec![Message::user("why is the sky blue?".to_owned())],
+                Default::default(),
+                vec![],
+                tx,
+                CancellationToken::new(),
+            )
+            .await
+            .unwrap();
+        let events = std::iter::from_fn(|| rx.try_recv().ok()).collect::<Vec<_>>();
+
+        assert!(matches!(events.first(), Some(Event::LLMEventStart(1))));
+        assert!(matches!(events.last(), Some(Event::LLMEventEnd(1))));
+        assert_eq!(answer_of(&events), OPENAI_ANSWER);
+        let Some(Event::LLMEventStats(1, stats)) = events.get(events.len() - 2) else {
+            panic!("no stats before the end: {:?}", events);
+        };
+        assert_eq!(stats.prompt_tokens, Some(14));
+        assert_eq!(stats.completion_tokens, Some(9));
+        assert_eq!(stats.stop_reason.as_deref(), Some("max_tokens"));
+        assert!(stats
+            .describe()
+            .starts_with("14+9 tok · stopped: max_tokens"));
+    }
+
+    #[tokio::test]
+    async fn anthropic_split_at_every_offset() {
+        let bytes = ANTHROPIC_SSE.as_bytes();
+        for i in 0..bytes.len() {
+            let (res, events) = read_anthropic(vec![&bytes[..i], &bytes[i..]]).await;
+            assert_eq!(res, Ok(()), "split at {}", i);
+            assert_eq!(answer_of(&events), OPENAI_ANSWER, "split at {}", i);
+        }
+    }
+
+    #[tokio::test]
+    async fn anthropic_error_event() {
+        let body = concat!(
+            "event: content_block_delta\n",
+            "data: {\"type\":\"content_block_delta\",\"index\":0,\"delta\":{\"type\":\"text_delta\",\"text\":\"Hel\"}}\n\n",
+            "event: error\n",
+            "data: {\"type\":\"error\",\"error\":{\"type\":\"overloaded_error\",\"message\":\"Overloaded\"}}\n\n",
+        );
+        let (res, events) = read_anthropic(vec![body.as_bytes()]).await;
+        assert_eq!(
+            res,
+            Err(LLMError::Api("overloaded_error: Overloaded".to_string()))
+        );
+        assert_eq!(answer_of(&events), "Hel");
+    }
+
+    #[test]
+    fn anthropic_body() {
+        let anthropic = Anthropic::new(Profile {
+            provider: ProviderKind::Anthropic,
+            model: "claude-3-5-sonnet-20240620".to_string(),
+            ..Default::default()
+        });
+        let history = [
+            Message::system("be brief".to_owned()),
+            Message::user("hi".to_owned()),
+            Message::assistant("hello".to_owned()),
+            Message::user("why?".to_owned()),
+        ];
+        let mut params = GenerationParams::default();
+        params.set("stop", "END").unwrap();
+        params.set("seed", "7").unwrap();
+
+        let data = anthropic.body(&history, &params);
+        assert_eq!(data["system"], "be brief");
+        assert_eq!(data["max_tokens"], 4096);
+        assert_eq!(data["stop_sequences"], serde_json::json!(["END"]));
+        assert!(data.get("seed").is_none());
+        let roles = data["messages"]
+            .as_array()
+            .unwrap()
+            .iter()
+            .map(|m| m["role"].as_str().unwrap())
+            .collect::<Vec<_>>();
+        assert_eq!(roles, ["user", "assistant", "user"]);
+    }
+
+    const GEMINI_SSE: &str = include_str!("../tests/fixtures/gemini_stream.sse");
+    const GEMINI_SAFETY: &str = include_str!("../tests/fixtures/gemini_safety.sse");
+
+    /// send a prompt to a gemini profile served by a mock replying `body`
+    async fn gemini_events(body: &'static str) -> (Result<(), LLMError>, Vec<Event>) {
+        let url = mock_server("text/event-stream", body, 7, false).await;
+        let mut gemini = Gemini::new(Profile {
+            provider: ProviderKind::Gemini,
+            endpoint: Some(format!(
+                "{}/v1beta/models/{{model}}:streamGenerateContent?alt=sse",
+                url
+            )),
+            model: "gemini-1.5-flash".to_string(),
+            ..Default::default()
+        });
+        let (tx, mut rx) = unbounded_channel();
+        let res = gemini
+            .request(
+                1,
+                vec![Message::user("why is the sky blue?".to_owned())],
+                Default::default(),
+                vec![],
+                tx,
+                CancellationToken::new(),
+            )
+            .await;
+        (res, std::iter::from_fn(|| rx.try_recv().ok()).collect())
+    }
+
+    #[tokio::test]
+    async fn gemini_stream_sse() {
+        let (res, events) = gemini_events(GEMINI_SSE).await;
+        assert_eq!(res, Ok(()));
+        assert!(matches!(events.first(), Some(Event::LLMEventStart(1))));
+        assert!(matches!(events.last(), Some(Event::LLMEventEnd(1))));
+        assert_eq!(answer_of(&events), OPENAI_ANSWER);
+        let Some(Event::LLMEventStats(1, stats)) = events.get(events.len() - 2) else {
+            panic!("no stats before the end: {:?}", events);
+        };
+        assert_eq!(stats.prompt_tokens, Some(8));
+        assert_eq!(stats.completion_tokens, Some(9));
+        assert_eq!(stats.stop_reason.as_deref(), Some("stop"));
+    }
+
+    #[tokio::test]
+    async fn gemini_safety_block() {
+        // the part streamed before the block is kept, the block is an error
+        let (res, events) = gemini_events(GEMINI_SAFETY).await;
+        assert_eq!(
+            res,
+            Err(LLMError::Blocked(
+                "SAFETY (HARM_CATEGORY_DANGEROUS_CONTENT)".to_string()
+            ))
+        );
+        assert_eq!(answer_of(&events), "Step one is to");
+        assert!(!events.iter().any(|ev| matches!(ev, Event::LLMEventEnd(_))));
+
+        let body = "data: {\"promptFeedback\": {\"blockReason\": \"PROHIBITED_CONTENT\"},\"usageMetadata\": {\"promptTokenCount\": 9,\"totalTokenCount\": 9}}\r\n\r\n";
+        let (res, events) = gemini_events(body).await;
+        let e = res.unwrap_err();
+        assert_eq!(
+            e.to_string(),
+            "blocked by safety filters: PROHIBITED_CONTENT"
+        );
+        assert!(!e.is_transient());
+        assert_eq!(answer_of(&events), "");
+    }
+
+    #[test]
+    fn gemini_body() {
+        let profile = Profile {
+            provider: ProviderKind::Gemini,
+            model: "gemini-1.5-flash".to_string(),
+            ..Default::default()
+        };
+        assert_eq!(
+            profile.endpoint(),
+            "https://generativelanguage.googleapis.com/v1beta/models/gemini-1.5-flash:streamGenerateContent?alt=sse"
+        );
+        let history = [
+            Message::system("be brief".to_owned()),
+            Message::user("hi".to_owned()),
+            Message::assistant("hello".to_owned()),
+            Message::user("why?".to_owned()),
+        ];
+        let mut params = GenerationParams::default();
+        params.set("max_tokens", "100").unwrap();
+        params.set("response_format", "json").unwrap();
+
+        let data = Gemini::new(profile).body(&history, &params);
+        assert_eq!(data["systemInstruction"]["parts"][0]["text"], "be brief");
+        assert_eq!(data["generationConfig"]["maxOutputTokens"], 100);
+        assert_eq!(
+            data["generationConfig"]["responseMimeType"],
+            "application/json"
+        );
+        let roles = data["contents"]
+            .as_array()
+            .unwrap()
+            .iter()
+            .map(|m| m["role"].as_str().unwrap())
+            .collect::<Vec<_>>();
+        assert_eq!(roles, ["user", "model", "user"]);
+        assert_eq!(data["contents"][1]["parts"][0]["text"], "hello");
+    }
+
+    const OPENAI_JSON: &str = include_str!("../tests/fixtures/openai_chat.json");
+
+    #[tokio::test]
+    async fn chatgpt_no_stream() {
+        let url = mock_server("application/json", OPENAI_JSON, 16, false).await;
+        let mut chatgpt = ChatGPT::new(Profile {
+            endpoint: Some(url),
+            model: "mixtral-8x7b-32768".to_string(),
+            stream: Some(false),
+            ..Default::default()
+        });
+        let data = chatgpt.body(&[], &Default::default(), &[]);
+        assert_eq!(data["stream"], false);
+        assert!(data.get("stream_options").is_none());
+
+        let (tx, mut rx) = unbounded_channel();
+        chatgpt
+            .request(
+                1,
+                vec![Message::user("why is the sky blue?".to_owned())],
+                Default::default(),
+                vec![],
+                tx,
+                CancellationToken::new(),
+            )
+            .await
+            .unwrap();
+        let events = std::iter::from_fn(|| rx.try_recv().ok()).collect::<Vec<_>>();
+        assert_eq!(events.len(), 4, "{:?}", events);
+        assert!(matches!(events[0], Event::LLMEventStart(1)));
+        assert_eq!(answer_of(&events), OPENAI_ANSWER);
+        let Event::LLMEventStats(1, ref stats) = events[2] else {
+            panic!("no stats: {:?}", events);
+        };
+        assert_eq!(stats.prompt_tokens, Some(16));
+        assert_eq!(stats.tokens_per_sec(), Some(336.0));
+        assert_eq!(stats.stop_reason.as_deref(), Some("length"));
+        assert!(matches!(events[3], Event::LLMEventEnd(1)));
+    }
+
+    #[tokio::test]
+    async fn chatgpt_json_fallback() {
+        let url = mock_replies(vec![
+            (200, "Content-Type: application/json\r\n", OPENAI_JSON),
+            (
+                200,
+                "Content-Type: application/json\r\n",
+                r#"{"error":{"message":"quota exceeded"}}"#,
+            ),
+        ])
+        .await;
+        let mut chatgpt = ChatGPT::new(Profile {
+            endpoint: Some(url),
+            model: "mixtral-8x7b-32768".to_string(),
+            ..Default::default()
+        });
+        assert_eq!(chatgpt.body(&[], &Default::default(), &[])["stream"], true);
+
+        for expected in [Ok(OPENAI_ANSWER), Err("server error: quota exceeded")] {
+            let (tx, mut rx) = unbounded_channel();
+            let res = chatgpt
+                .request(
+                    1,
+                    vec![Message::user("why is the sky blue?".to_owned())],
+                    Default::default(),
+                    vec![],
+                    tx,
+                    CancellationToken::new(),
+                )
+                .await;
+            let events = std::iter::from_fn(|| rx.try_recv().ok()).collect::<Vec<_>>();
+            match expected {
+                Ok(answer) => {
+                    assert_eq!(res, Ok(()));
+                    assert_eq!(answer_of(&events), answer);
+                }
+                Err(e) => assert_eq!(res.unwrap_err().to_string(), e),
+            }
+            // the plain json answer switched it off for good
+            assert_eq!(chatgpt.body(&[], &Default::default(), &[])["stream"], false);
+        }
+    }
+
+    #[tokio::test]
+    async fn chatgpt_stream_tool_calls() {
+        let (tx, mut rx) = unbounded_channel();
+        let body = include_str!("../tests/fixtures/openai_tool_calls.sse");
+        let chunks = body.as_bytes().chunks(7).map(Ok::<_, LLMError>);
+        let res = read_stream(
+            1,
+            futures::stream::iter(chunks),
+            Timer::start(),
+            &tx,
+            &CancellationToken::new(),
+        )
+        .await;
+        assert_eq!(res, Ok(()));
+
+        let mut calls = Vec::new();
+        let mut stats = None;
+        while let Ok(ev) = rx.try_recv() {
+            match ev {
+                Event::LLMEventDelta(_, msg) => {
+                    merge_tool_calls(&mut calls, msg.tool_calls.unwrap_or_default())
+                }
+                Event::LLMEventStats(_, s) => stats = Some(s),
+                _ => {}
+            }
+        }
+        let calls = calls
+            .iter()
+            .map(|c| {
+                (
+                    c.id.as_deref(),
+                    c.function.name.as_deref(),
+                    c.function.arguments.as_str(),
+                )
+            })
+            .collect::<Vec<_>>();
+        assert_eq!(
+            calls,
+            [
+                (Some("call_a1"), Some("list_dir"), r#"{"path": "src"}"#),
+                (
+                    Some("call_b2"),
+                    Some("read_file"),
+                    r#"{"path": "Cargo.toml"}"#
+                ),
+            ]
+        );
+        let stats = stats.unwrap();
+        assert_eq!(stats.stop_reason.as_deref(), Some("tool_calls"));
+        // asking for tools is a natural end
+        assert!(!stats.describe().contains("stopped"));
+    }
+
+    #[test]
+    fn chatgpt_body_tools() {
+        let chatgpt = ChatGPT::new(Profile::default());
+        let call = ToolCall {
+            id: Some("call_a1".to_owned()),
+            function: FunctionCall {
+                name: Some("list_dir".to_owned()),
+                arguments: r#"{"path":"."}"#.to_owned(),
+            },
+            ..Default::default()
+        };
+        let mut answer = Message::assistant(String::new());
+        answer.tool_calls = Some(vec![call.clone()]);
+        let history = [
+            Message::user("what is here?".to_owned()),
+            answer,
+            Message::tool(&call, "src/".to_owned()),
+        ];
+        let tools = crate::tool::ToolRegistry::builtin().only(&["list_dir".to_owned()]);
+        let data = chatgpt.body(&history, &Default::default(), &tools.specs());
+
+        assert_eq!(data["tools"].as_array().unwrap().len(), 1);
+        assert_eq!(data["tools"][0]["type"], "function");
+        assert_eq!(data["tools"][0]["function"]["name"], "list_dir");
+        assert_eq!(
+            data["tools"][0]["function"]["parameters"]["required"][0],
+            "path"
+        );
+
+        let messages = &data["messages"];
+        assert!(messages[1]["content"].is_null());
+        assert_eq!(messages[1]["tool_calls"][0]["id"], "call_a1");
+        assert_eq!(messages[1]["tool_calls"][0]["type"], "function");
+        assert_eq!(
+            messages[1]["tool_calls"][0]["function"]["arguments"],
+            r#"{"path":"."}"#
+        );
+        assert_eq!(messages[2]["role"], "tool");
+        assert_eq!(messages[2]["tool_call_id"], "call_a1");
+        assert_eq!(messages[2]["content"], "src/");
+
+        // no tools, no empty array either
+        assert!(chatgpt
+            .body(&history, &Default::default(), &[])
+            .get("tools")
+            .is_none());
     }
 }
@@ -1,16 +1,53 @@
 use dotenv::dotenv;
-use llmi::{app::App, term::Term};
+use llmi::{app::App, config::Config, highlight, session::Session, term::Term};
 use ratatui::{backend::CrosstermBackend, Terminal};
-use std::io::{stdout, Result};
+use std::io::{stdout, Error, ErrorKind, Result};
+
+const USAGE: &str = "usage: llmi [--resume | --session <id>]
+
+  --resume          continue the most recent session
+  --session <id>    open the saved session <id>
+  -h, --help        print this help";
+
+/// the session to open on startup, if any
+fn parse_args() -> Result<Option<Session>> {
+    let mut args = std::env::args().skip(1);
+    match args.next().as_deref() {
+        None => Ok(None),
+        Some("--resume") => Session::latest(),
+        Some("--session") => {
+            let id = args
+                .next()
+                .ok_or_else(|| Error::new(ErrorKind::InvalidInput, USAGE))?;
+            Session::load(&id)
+                .map(Some)
+                .map_err(|e| Error::new(e.kind(), format!("failed to load session {}: {}", id, e)))
+        }
+        Some("-h" | "--help") => {
+            println!("{}", USAGE);
+            std::process::exit(0);
+        }
+        Some(arg) => Err(Error::new(
+            ErrorKind::InvalidInput,
+            format!("unknown argument {}\n{}", arg, USAGE),
+        )),
+    }
+}
 
 #[tokio::main]
 async fn main() -> Result<()> {
     dotenv().ok();
+    let config = Config::load()?;
+    highlight::init(config.theme.as_deref())?;
+    let session = parse_args()?;
 
     let mut term = Term::new(Terminal::new(CrosstermBackend::new(stdout()))?);
     term.init()?;
 
-    let mut app = App::new();
+    let mut app = App::new(config);
+    if let Some(session) = session {
+        app.open_session(session);
+    }
     term.run(&mut app).await?;
 
     term.exit()?;
@@ -0,0 +1,617 @@
+use pulldown_cmark::{Alignment, CodeBlockKind, Event, HeadingLevel, Options, Parser, Tag, TagEnd};
+use ratatui::{
+    style::{Color, Style, Stylize},
+    text::{Line, Span},
+};
+use unicode_width::{UnicodeWidthChar, UnicodeWidthStr};
+
+use crate::highlight;
+
+/// render markdown `content` into lines no wider than `width` columns, the
+/// number of lines returned is the height the content needs on screen.
+pub fn render(content: &str, width: u16) -> Vec<Line<'static>> {
+    let mut renderer = Renderer::new(width.max(1) as usize);
+    let options =
+        Options::ENABLE_TABLES | Options::ENABLE_STRIKETHROUGH | Options::ENABLE_TASKLISTS;
+    for event in Parser::new_ext(content, options) {
+        renderer.process(event);
+    }
+    renderer.finish()
+}
+
+/// render `content` as is, only wrapping it to `width` columns
+pub fn render_plain(content: &str, width: u16) -> Vec<Line<'static>> {
+    content
+        .split('\n')
+        .flat_map(|ln| wrap(vec![Span::raw(ln.to_owned())], width.max(1) as usize))
+        .map(Line::from)
+        .collect()
+}
+
+/// the contents of the fenced and indented code blocks in `content`
+pub fn code_blocks(content: &str) -> Vec<String> {
+    let mut blocks = Vec::new();
+    let mut code: Option<String> = None;
+    for event in Parser::new(content) {
+        match event {
+            Event::Start(Tag::CodeBlock(_)) => code = Some(String::new()),
+            Event::Text(text) => {
+                if let Some(code) = code.as_mut() {
+                    code.push_str(&text);
+                }
+            }
+            Event::End(TagEnd::CodeBlock) => blocks.extend(code.take()),
+            _ => {}
+        }
+    }
+    blocks
+}
+
+struct Table {
+    alignments: Vec<Alignment>,
+    rows: Vec<Vec<String>>,
+    cell: Option<String>,
+}
+
+struct Renderer {
+    width: usize,
+    lines: Vec<Line<'static>>,
+    spans: Vec<Span<'static>>,
+    styles: Vec<Style>,
+    /// marker width of every open list, the number of the next item for ordered ones
+    lists: Vec<(Option<u64>, usize)>,
+    /// marker of the current list item, until its first line is written
+    marker: Option<String>,
+    quote: usize,
+    code: Option<(String, String)>,
+    table: Option<Table>,
+    link: Option<String>,
+    /// a blank line is due before the next block
+    gap: bool,
+}
+
+impl Renderer {
+    fn new(width: usize) -> Self {
+        Self {
+            width,
+            lines: Vec::new(),
+            spans: Vec::new(),
+            styles: vec![Style::default()],
+            lists: Vec::new(),
+            marker: None,
+            quote: 0,
+            code: None,
+            table: None,
+            link: None,
+            gap: false,
+        }
+    }
+
+    fn style(&self) -> Style {
+        *self.styles.last().unwrap()
+    }
+
+    fn push_style(&mut self, style: Style) {
+        self.styles.push(self.style().patch(style));
+    }
+
+    fn pop_style(&mut self) {
+        if self.styles.len() > 1 {
+            self.styles.pop();
+        }
+    }
+
+    fn process(&mut self, event: Event) {
+        match event {
+            Event::Start(tag) => self.start(tag),
+            Event::End(tag) => self.end(tag),
+            Event::Text(text) => self.text(&text),
+            Event::Code(code) => {
+                let style = self.style().patch(Style::new().fg(Color::Yellow));
+                self.push_span(Span::styled(code.into_string(), style));
+            }
+            Event::Html(html) | Event::InlineHtml(html) => self.text(&html),
+            Event::InlineMath(math) | Event::DisplayMath(math) => self.text(&math),
+            Event::FootnoteReference(name) => self.text(&format!("[^{}]", name)),
+            Event::SoftBreak => self.text(" "),
+            Event::HardBreak => self.push_span(Span::raw("\n")),
+            Event::Rule => {
+                self.block_start();
+                let width = self.width.saturating_sub(self.prefix_width());
+                self.spans
+                    .push(Span::styled("─".repeat(width), Style::new().dark_gray()));
+                self.flush();
+                self.gap = true;
+            }
+            Event::TaskListMarker(checked) => {
+                let mark = if checked { "[x] " } else { "[ ] " };
+                self.push_span(Span::styled(mark, Style::new().cyan()));
+            }
+        }
+    }
+
+    fn start(&mut self, tag: Tag) {
+        match tag {
+            Tag::Paragraph => self.block_start(),
+            Tag::Heading { level, .. } => {
+                self.block_start();
+                let style = match level {
+                    HeadingLevel::H1 => Style::new().magenta().bold().underlined(),
+                    HeadingLevel::H2 => Style::new().cyan().bold(),
+                    _ => Style::new().bold(),
+                };
+                self.push_style(style);
+            }
+            Tag::BlockQuote(_) => {
+                self.block_start();
+                self.quote += 1;
+                self.push_style(Style::new().italic());
+            }
+            Tag::CodeBlock(kind) => {
+                self.block_start();
+                let lang = match kind {
+                    CodeBlockKind::Fenced(lang) => {
+                        lang.split(',').next().unwrap_or("").trim().to_owned()
+                    }
+                    CodeBlockKind::Indented => String::new(),
+                };
+                self.code = Some((lang, String::new()));
+            }
+            Tag::HtmlBlock => self.block_start(),
+            Tag::List(start) => {
+                if self.lists.is_empty() {
+                    self.block_start();
+                } else {
+                    self.flush();
+                }
+                let width = match start {
+                    Some(n) => format!("{}. ", n).width().max(3),
+                    None => 2,
+                };
+                self.lists.push((start, width));
+            }
+            Tag::Item => {
+                self.flush();
+                self.gap = false;
+                let depth = self.lists.len();
+                if let Some((number, width)) = self.lists.last_mut() {
+                    let marker = match number {
+                        Some(n) => {
+                            *n += 1;
+                            format!("{:<w$}", format!("{}.", *n - 1), w = *width)
+                        }
+                        None => match depth {
+                            1 => "• ",
+                            2 => "◦ ",
+                            _ => "▪ ",
+                        }
+                        .to_owned(),
+                    };
+                    self.marker = Some(marker);
+                }
+            }
+            Tag::FootnoteDefinition(name) => {
+                self.block_start();
+                self.text(&format!("[^{}]: ", name));
+            }
+            Tag::DefinitionList | Tag::DefinitionListTitle | Tag::DefinitionListDefinition => {
+                self.block_start()
+            }
+            Tag::Table(alignments) => {
+                self.block_start();
+                self.table = Some(Table {
+                    alignments,
+                    rows: Vec::new(),
+                    cell: None,
+                });
+            }
+            Tag::TableHead | Tag::TableRow => {
+                if let Some(ref mut table) = self.table {
+                    table.rows.push(Vec::new());
+                }
+            }
+            Tag::TableCell => {
+                if let Some(ref mut table) = self.table {
+                    table.cell = Some(String::new());
+                }
+            }
+            Tag::Emphasis => self.push_style(Style::new().italic()),
+            Tag::Strong => self.push_style(Style::new().bold()),
+            Tag::Strikethrough => self.push_style(Style::new().crossed_out()),
+            Tag::Link { dest_url, .. } => {
+                self.link = Some(dest_url.into_string());
+                self.push_style(Style::new().blue().underlined());
+            }
+            Tag::Image { dest_url, .. } => {
+                self.link = Some(dest_url.into_string());
+                self.push_style(Style::new().blue());
+                self.text("[img] ");
+            }
+            Tag::MetadataBlock(_) => {}
+        }
+    }
+
+    fn end(&mut self, tag: TagEnd) {
+        match tag {
+            TagEnd::Paragraph | TagEnd::HtmlBlock | TagEnd::FootnoteDefinition => self.block_end(),
+            TagEnd::Heading(_) => {
+                self.pop_style();
+                self.block_end();
+            }
+            TagEnd::BlockQuote(_) => {
+                self.flush();
+                self.quote = self.quote.saturating_sub(1);
+                self.pop_style();
+                self.gap = true;
+            }
+            TagEnd::CodeBlock => {
+                if let Some((lang, code)) = self.code.take() {
+                    self.code_block(&lang, &code);
+                }
+                self.gap = true;
+            }
+            TagEnd::List(_) => {
+                self.flush();
+                self.lists.pop();
+                self.gap = self.lists.is_empty();
+            }
+            TagEnd::Item => {
+                self.flush();
+                self.marker = None;
+            }
+            TagEnd::DefinitionList
+            | TagEnd::DefinitionListTitle
+            | TagEnd::DefinitionListDefinition => self.block_end(),
+            TagEnd::Table => {
+                if let Some(table) = self.table.take() {
+                    self.table_block(table);
+                }
+                self.gap = true;
+            }
+            TagEnd::TableHead | TagEnd::TableRow => {}
+            TagEnd::TableCell => {
+                if let Some(ref mut table) = self.table {
+                    let cell = table.cell.take().unwrap_or_default();
+                    if let Some(row) = table.rows.last_mut() {
+                        row.push(cell);
+                    }
+                }
+            }
+            TagEnd::Emphasis | TagEnd::Strong | TagEnd::Strikethrough => self.pop_style(),
+            TagEnd::Link | TagEnd::Image => {
+                self.pop_style();
+                if let Some(url) = self.link.take() {
+                    let shown = self.spans.last().is_some_and(|s| s.content == url);
+                    if !shown && !url.is_empty() {
+                        self.push_span(Span::styled(
+                            format!(" ({})", url),
+                            Style::new().dark_gray(),
+                        ));
+                    }
+                }
+            }
+            TagEnd::MetadataBlock(_) => {}
+        }
+    }
+
+    fn text(&mut self, text: &str) {
+        if let Some((_, ref mut code)) = self.code {
+            code.push_str(text);
+        } else if let Some(cell) = self.table.as_mut().and_then(|t| t.cell.as_mut()) {
+            cell.push_str(text);
+        } else {
+            let style = self.style();
+            self.push_span(Span::styled(text.to_owned(), style));
+        }
+    }
+
+    fn push_span(&mut self, span: Span<'static>) {
+        if let Some(cell) = self.table.as_mut().and_then(|t| t.cell.as_mut()) {
+            cell.push_str(&span.content);
+        } else {
+            self.spans.push(span);
+        }
+    }
+
+    fn block_start(&mut self) {
+        self.flush();
+        if self.gap && !self.lines.is_empty() {
+            let prefix = self.prefix(false);
+            self.lines.push(Line::from(prefix));
+        }
+        self.gap = false;
+    }
+
+    fn block_end(&mut self) {
+        self.flush();
+        self.gap = true;
+    }
+
+    /// quote bars and list indentation put in front of every line, `first`
+    /// for the first line of a list item which carries its marker
+    fn prefix(&mut self, first: bool) -> Vec<Span<'static>> {
+        let mut prefix = Vec::new();
+        if self.quote > 0 {
+            prefix.push(Span::styled(
+                "│ ".repeat(self.quote),
+                Style::new().dark_gray(),
+            ));
+        }
+        if let Some(((_, last), parents)) = self.lists.split_last() {
+            let indent = parents.iter().map(|(_, w)| w).sum::<usize>();
+            prefix.push(Span::raw(" ".repeat(indent)));
+            match self.marker.take() {
+                Some(marker) if first => prefix.push(Span::styled(marker, Style::new().cyan())),
+                marker => {
+                    self.marker = marker;
+                    prefix.push(Span::raw(" ".repeat(*last)));
+                }
+            }
+        }
+        prefix
+    }
+
+    fn prefix_width(&self) -> usize {
+        self.quote * 2 + self.lists.iter().map(|(_, w)| w).sum::<usize>()
+    }
+
+    /// wrap the pending spans into lines
+    fn flush(&mut self) {
+        if self.spans.is_empty() && self.marker.is_none() {
+            return;
+        }
+
+        let spans = std::mem::take(&mut self.spans);
+        let width = self.width.saturating_sub(self.prefix_width()).max(1);
+        for (i, spans) in wrap(spans, width).into_iter().enumerate() {
+            let mut line = self.prefix(i == 0);
+            line.extend(spans);
+            self.lines.push(Line::from(line));
+        }
+    }
+
+    /// highlighted code on its own background, headed by the language label
+    fn code_block(&mut self, lang: &str, code: &str) {
+        let bg = Style::new().bg(highlight::background());
+        let width = self.width.saturating_sub(self.prefix_width()).max(1);
+
+        let mut rows = Vec::new();
+        if !lang.is_empty() {
+            let label = Span::styled(format!(" {} ", lang), bg.fg(Color::DarkGray).italic());
+            rows.extend(wrap_hard(vec![label], width));
+        }
+        for spans in highlight::highlight(code, lang) {
+            rows.extend(wrap_hard(spans, width));
+        }
+
+        for mut spans in rows {
+            let used = spans.iter().map(|s| s.width()).sum::<usize>();
+            spans.push(Span::styled(" ".repeat(width.saturating_sub(used)), bg));
+            let mut line = self.prefix(false);
+            line.extend(spans);
+            self.lines.push(Line::from(line));
+        }
+    }
+
+    fn table_block(&mut self, table: Table) {
+        let columns = table.rows.iter().map(|r| r.len()).max().unwrap_or(0);
+        if columns == 0 {
+            return;
+        }
+
+        let available = self
+            .width
+            .saturating_sub(self.prefix_width())
+            .saturating_sub(3 * (columns - 1));
+        let natural = (0..columns)
+            .map(|c| {
+                table
+                    .rows
+                    .iter()
+                    .filter_map(|r| r.get(c))
+                    .map(|cell| cell.trim().width())
+                    .max()
+                    .unwrap_or(0)
+                    .max(1)
+            })
+            .collect::<Vec<_>>();
+        let widths = fit_columns(&natural, available);
+
+        let separator = Style::new().dark_gray();
+        for (i, row) in table.rows.iter().enumerate() {
+            let cells = (0..columns)
+                .map(|c| {
+                    let text = row.get(c).map(|s| s.trim()).unwrap_or("");
+                    wrap(vec![Span::raw(text.to_owned())], widths[c])
+                })
+                .collect::<Vec<_>>();
+            let height = cells.iter().map(|c| c.len()).max().unwrap_or(1);
+
+            for h in 0..height {
+                let mut line = self.prefix(false);
+                for (c, cell) in cells.iter().enumerate() {
+                    if c > 0 {
+                        line.push(Span::styled(" │ ", separator));
+                    }
+                    let text = cell
+                        .get(h)
+                        .map(|spans| spans.iter().map(|s| s.content.as_ref()).collect::<String>())
+                        .unwrap_or_default();
+                    let pad = widths[c].saturating_sub(text.width());
+                    let (left, right) = match table.alignments.get(c) {
+                        Some(Alignment::Right) => (pad, 0),
+                        Some(Alignment::Center) => (pad / 2, pad - pad / 2),
+                        _ => (0, pad),
+                    };
+                    let style = if i == 0 {
+                        Style::new().bold()
+                    } else {
+                        Style::new()
+                    };
+                    line.push(Span::styled(
+                        format!("{}{}{}", " ".repeat(left), text, " ".repeat(right)),
+                        style,
+                    ));
+                }
+                self.lines.push(Line::from(line));
+            }
+
+            if i == 0 {
+                let rule = widths
+                    .iter()
+                    .map(|w| "─".repeat(*w))
+                    .collect::<Vec<_>>()
+                    .join("─┼─");
+                let mut line = self.prefix(false);
+                line.push(Span::styled(rule, separator));
+                self.lines.push(Line::from(line));
+            }
+        }
+    }
+
+    fn finish(mut self) -> Vec<Line<'static>> {
+        // an unterminated code fence while streaming
+        if let Some((lang, code)) = self.code.take() {
+            self.code_block(&lang, &code);
+        }
+        if let Some(table) = self.table.take() {
+            self.table_block(table);
+        }
+        self.flush();
+        self.lines
+    }
+}
+
+/// shrink the natural column widths to fit `available` columns, narrow
+/// columns keep their width and the wide ones share what is left.
+fn fit_columns(natural: &[usize], available: usize) -> Vec<usize> {
+    if natural.iter().sum::<usize>() <= available {
+        return natural.to_vec();
+    }
+
+    let mut widths = vec![0; natural.len()];
+    let mut left = available;
+    let mut open = (0..natural.len()).collect::<Vec<_>>();
+    while !open.is_empty() {
+        let share = (left / open.len()).max(1);
+        let (fits, wide): (Vec<usize>, Vec<usize>) =
+            open.iter().partition(|c| natural[**c] <= share);
+        if fits.is_empty() {
+            for c in &wide {
+                widths[*c] = share;
+            }
+            break;
+        }
+        for c in fits {
+            widths[c] = natural[c];
+            left = left.saturating_sub(natural[c]);
+        }
+        open = wide;
+    }
+    widths
+}
+
+/// word wrap spans to `width` columns, a "\n" span forces a line break
+fn wrap(spans: Vec<Span<'static>>, width: usize) -> Vec<Vec<Span<'static>>> {
+    let mut lines = vec![Vec::new()];
+    let mut used = 0;
+
+    for span in spans {
+        if span.content == "\n" {
+            lines.push(Vec::new());
+            used = 0;
+            continue;
+        }
+
+        for word in split_words(&span.content) {
+            let w = word.width();
+            let blank = word.trim().is_empty();
+            if used + word.trim_end().width() > width && used > 0 {
+                lines.push(Vec::new());
+                used = 0;
+                if blank {
+                    continue;
+                }
+            }
+            if w > width {
+                for part in wrap_hard(vec![Span::styled(word.to_owned(), span.style)], width) {
+                    if used > 0 {
+                        lines.push(Vec::new());
+                    }
+                    used = part.iter().map(|s| s.width()).sum();
+                    lines.last_mut().unwrap().extend(part);
+                }
+                continue;
+            }
+            lines
+                .last_mut()
+                .unwrap()
+                .push(Span::styled(word.to_owned(), span.style));
+            used += w;
+        }
+    }
+
+    // whitespace the line was broken at
+    for line in lines.iter_mut() {
+        while let Some(last) = line.last_mut() {
+            let trimmed = last.content.trim_end().to_owned();
+            if trimmed.is_empty() {
+                line.pop();
+            } else {
+                last.content = trimmed.into();
+                break;
+            }
+        }
+    }
+
+    lines
+}
+
+/// split into words each followed by its trailing whitespace
+fn split_words(s: &str) -> Vec<&str> {
+    let mut words = Vec::new();
+    let mut start = 0;
+    let mut in_space = false;
+    for (i, c) in s.char_indices() {
+        if c.is_whitespace() {
+            in_space = true;
+        } else if in_space {
+            words.push(&s[start..i]);
+            start = i;
+            in_space = false;
+        }
+    }
+    if start < s.len() {
+        words.push(&s[start..]);
+    }
+    words
+}
+
+/// break spans at exactly `width` columns regardless of words
+fn wrap_hard(spans: Vec<Span<'static>>, width: usize) -> Vec<Vec<Span<'static>>> {
+    let mut lines = vec![Vec::new()];
+    let mut used = 0;
+    for span in spans {
+        let mut part = String::new();
+        for c in span.content.chars() {
+            let w = c.width().unwrap_or(0);
+            if used + w > width && used > 0 {
+                lines
+                    .last_mut()
+                    .unwrap()
+                    .push(Span::styled(std::mem::take(&mut part), span.style));
+                lines.push(Vec::new());
+                used = 0;
+            }
+            part.push(c);
+            used += w;
+        }
+        if !part.is_empty() {
+            lines
+                .last_mut()
+                .unwrap()
+                .push(Span::styled(part, span.style));
+        }
+    }
+    lines
+}
@@ -0,0 +1,66 @@
+#[cfg(test)]
+mod tests {
+    use crate::markdown::{code_blocks, render};
+    use ratatui::text::Line;
+
+    fn plain(lines: &[Line]) -> Vec<String> {
+        lines
+            .iter()
+            .map(|ln| ln.spans.iter().map(|s| s.content.as_ref()).collect())
+            .collect()
+    }
+
+    #[test]
+    fn markdown_blocks() {
+        let lines = render(
+            "## Steps\n\n1. **build** it\n2. run\n   - fast\n\n> note",
+            40,
+        );
+        assert_eq!(
+            plain(&lines),
+            vec![
+                "Steps",
+                "",
+                "1. build it",
+                "2. run",
+                "   ◦ fast",
+                "",
+                "│ note"
+            ]
+        );
+    }
+
+    #[test]
+    fn markdown_table_fits_width() {
+        let content =
+            "| key | description |\n|---|---|\n| a | a rather long description of the key |";
+        let lines = render(content, 24);
+        assert!(lines.iter().all(|ln| ln.width() <= 24));
+        assert_eq!(plain(&lines)[1], "────┼───────────────────");
+        assert_eq!(lines.len(), 5);
+    }
+
+    #[test]
+    fn markdown_unterminated_fence() {
+        let lines = render("look:\n\n```python\nprint(1)\nprint(", 20);
+        let text = plain(&lines);
+        assert_eq!(text.len(), 5);
+        assert!(text[2].starts_with(" python "));
+        assert!(text[3].starts_with("print(1)"));
+        assert!(text[4].starts_with("print("));
+
+        // the line still streaming in is highlighted and padded like the rest
+        assert!(lines[4].spans.iter().any(|s| s.style.fg.is_some()));
+        assert!(lines.iter().skip(2).all(|ln| ln.width() == 20));
+    }
+
+    #[test]
+    fn markdown_code_blocks() {
+        let content =
+            "run:\n\n```sh\ncargo build\ncargo test\n```\n\nthen\n\n    indented\n\n```py\nprint(";
+        assert_eq!(
+            code_blocks(content),
+            vec!["cargo build\ncargo test\n", "indented\n", "print("]
+        );
+    }
+}
@@ -0,0 +1,185 @@
+use async_trait::async_trait;
+use futures::{stream, Stream, StreamExt};
+use reqwest::{header::CONTENT_TYPE, Client};
+use serde::Deserialize;
+use serde_json::{json, Value};
+use tokio::sync::mpsc::UnboundedSender;
+use tokio_util::sync::CancellationToken;
+
+use crate::config::{GenerationParams, Profile};
+use crate::error::LLMError;
+use crate::event::{Event, StreamId};
+use crate::llm::*;
+use crate::tool::ToolSpec;
+
+// Ollama streams `/api/chat` as newline delimited json, one object per line:
+// ```json
+// {"model":"llama3.1","created_at":"2024-08-20T09:41:15.1Z","message":{"role":"assistant","content":"Hello"},"done":false}
+// {"model":"llama3.1","created_at":"2024-08-20T09:41:15.4Z","message":{"role":"assistant","content":""},"done_reason":"stop","done":true,"prompt_eval_count":26,"eval_count":22,"eval_duration":412000000}
+// ```
+#[derive(Debug, Deserialize)]
+struct OllamaChunk {
+    message: Option<Message>,
+    error: Option<String>,
+    prompt_eval_count: Option<u64>,
+    eval_count: Option<u64>,
+    /// nanoseconds
+    eval_duration: Option<u64>,
+}
+
+impl OllamaChunk {
+    /// the counts of the last line in the shape openai reports them
+    fn usage(&self) -> Option<Usage> {
+        let completion_tokens = self.eval_count?;
+        let prompt_tokens = self.prompt_eval_count.unwrap_or_default();
+        Some(Usage {
+            prompt_tokens,
+            completion_tokens,
+            completion_time: self.eval_duration.unwrap_or_default() as f64 / 1e9,
+            total_tokens: prompt_tokens + completion_tokens,
+            ..Default::default()
+        })
+    }
+}
+
+#[derive(Debug)]
+pub struct Ollama {
+    cli: Client,
+    profile: Profile,
+}
+
+impl Ollama {
+    pub fn new(profile: Profile) -> Self {
+        Self {
+            cli: http_client(),
+            profile,
+        }
+    }
+
+    pub(crate) fn body(&self, history: &[Message], params: &GenerationParams) -> Value {
+        let messages = history
+            .iter()
+            .map(|msg| json!({ "role": msg.role, "content": msg.content }))
+            .collect::<Vec<_>>();
+
+        let mut data = json!({
+            "model": self.profile.model,
+            "stream": true,
+            "messages": messages,
+        });
+        // sampling settings go into `options`, max_tokens is called num_predict
+        let options = [
+            ("num_predict", json!(params.max_tokens)),
+            ("temperature", json!(params.temperature)),
+            ("top_p", json!(params.top_p)),
+            ("stop", json!(params.stop)),
+            ("seed", json!(params.seed)),
+            ("presence_penalty", json!(params.presence_penalty)),
+            ("frequency_penalty", json!(params.frequency_penalty)),
+        ];
+        for (name, value) in options {
+            if !value.is_null() {
+                data["options"][name] = value;
+            }
+        }
+        if params.response_format.as_deref() == Some("json") {
+            data["format"] = json!("json");
+        }
+        data
+    }
+
+    /// handle one complete ndjson line, the last one carries the usage
+    fn process_line(
+        id: StreamId,
+        line: &[u8],
+        timer: &mut Timer,
+        tx: &UnboundedSender<Event>,
+    ) -> Result<Option<Usage>, LLMError> {
+        let line = std::str::from_utf8(line)?;
+        let line = line.trim();
+        if line.is_empty() {
+            return Ok(None);
+        }
+
+        let chunk = serde_json::from_str::<OllamaChunk>(line)?;
+        if let Some(e) = chunk.error {
+            return Err(LLMError::Api(e));
+        }
+        let usage = chunk.usage();
+        if let Some(msg) = chunk.message {
+            if msg.content.as_deref().is_some_and(|s| !s.is_empty()) {
+                timer.token();
+                tx.send(Event::LLMEventDelta(id, msg)).unwrap();
+            }
+        }
+        Ok(usage)
+    }
+}
+
+#[async_trait]
+impl LLMService for Ollama {
+    async fn request(
+        &mut self,
+        id: StreamId,
+        history: Vec<Message>,
+        params: GenerationParams,
+        _tools: Vec<ToolSpec>,
+        tx: UnboundedSender<Event>,
+        cancel: CancellationToken,
+    ) -> Result<(), LLMError> {
+        let data = self.body(&history, &params);
+
+        tx.send(Event::LLMEventStart(id)).unwrap();
+
+        let timer = Timer::start();
+        let endpoint = self.profile.endpoint();
+        let req = || {
+            self.cli
+                .post(&endpoint)
+                .header(CONTENT_TYPE, "application/json")
+                .json(&data)
+        };
+        let Some(resp) = send_with_retry(id, &self.profile.retry, req, &tx, &cancel).await? else {
+            tx.send(Event::LLMEventCancelled(id)).unwrap();
+            return Ok(());
+        };
+
+        let lines = lines(resp.bytes_stream());
+        read_events(id, lines, timer, &tx, &cancel, |line, reply| {
+            let usage = Self::process_line(id, &line, &mut reply.timer, &tx)?;
+            reply.usage = usage.or(reply.usage.take());
+            Ok(Flow::Continue)
+        })
+        .await
+    }
+}
+
+/// the lines of a newline delimited body. a line may be split across
+/// several chunks, so bytes are only passed on up to the last newline seen
+/// and the rest is kept for later.
+fn lines<S, B, E>(body: S) -> impl Stream<Item = Result<Vec<u8>, LLMError>>
+where
+    S: Stream<Item = Result<B, E>>,
+    B: AsRef<[u8]>,
+    E: Into<LLMError>,
+{
+    let mut buf: Vec<u8> = Vec::new();
+    // `None` marks the end, a last line may come without a newline
+    body.map(Some)
+        .chain(stream::once(async { None }))
+        .flat_map(move |chunk| {
+            let mut lines = Vec::new();
+            match chunk {
+                Some(Ok(bytes)) => {
+                    buf.extend_from_slice(bytes.as_ref());
+                    while let Some(pos) = buf.iter().position(|b| *b == b'\n') {
+                        lines.push(Ok(buf.drain(..=pos).collect()));
+                    }
+                }
+                Some(Err(e)) => lines.push(Err(e.into())),
+                None if !buf.is_empty() => lines.push(Ok(std::mem::take(&mut buf))),
+                None => {}
+            }
+            stream::iter(lines)
+        })
+}
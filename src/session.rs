@@ -0,0 +1,169 @@
+use chrono::{DateTime, Local};
+use serde::{Deserialize, Serialize};
+use std::{
+    collections::hash_map::RandomState,
+    env, fs,
+    hash::BuildHasher,
+    io::{Error, ErrorKind, Result},
+    path::{Path, PathBuf},
+};
+
+use crate::conversation::Conversation;
+
+/// `$LLMI_DATA_DIR` or `<data dir>/llmi`
+pub fn data_dir() -> Result<PathBuf> {
+    env::var_os("LLMI_DATA_DIR")
+        .map(PathBuf::from)
+        .or_else(|| dirs::data_dir().map(|dir| dir.join("llmi")))
+        .ok_or_else(|| Error::new(ErrorKind::NotFound, "no data directory"))
+}
+
+/// a conversation persisted as `<data dir>/llmi/sessions/<id>.json`
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Session {
+    pub id: String,
+    pub name: String,
+    pub created: DateTime<Local>,
+    pub updated: DateTime<Local>,
+    #[serde(alias = "messages")]
+    pub conversation: Conversation,
+    /// system prompt sent ahead of the conversation
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub system: Option<String>,
+    /// the persona `system` was taken from, if any
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub persona: Option<String>,
+}
+
+impl Session {
+    pub fn new() -> Self {
+        let now = Local::now();
+        // sessions started within the same second, by this or another
+        // instance, must not share a file
+        let suffix = RandomState::new().hash_one(now) % 0x10000;
+        Self {
+            id: format!("{}-{:04x}", now.format("%Y%m%d-%H%M%S"), suffix),
+            name: String::new(),
+            created: now,
+            updated: now,
+            conversation: Conversation::default(),
+            system: None,
+            persona: None,
+        }
+    }
+
+    /// prompt and completion tokens of all answers, alternatives included
+    pub fn tokens(&self) -> (u64, u64) {
+        self.conversation
+            .all()
+            .filter_map(|msg| msg.stats.as_ref())
+            .fold((0, 0), |(prompt, completion), stats| {
+                (
+                    prompt + stats.prompt_tokens.unwrap_or_default(),
+                    completion + stats.completion_tokens.unwrap_or_default(),
+                )
+            })
+    }
+
+    /// dollars spent on all answers, as far as their price is known
+    pub fn cost(&self) -> f64 {
+        self.conversation
+            .all()
+            .filter_map(|msg| msg.stats.as_ref()?.cost)
+            .sum()
+    }
+
+    /// `$LLMI_DATA_DIR/sessions` or `<data dir>/llmi/sessions`
+    pub fn dir() -> Result<PathBuf> {
+        Ok(data_dir()?.join("sessions"))
+    }
+
+    /// ids come from the command line and `/load`, one must not name a
+    /// file outside `dir`
+    fn path(dir: &Path, id: &str) -> Result<PathBuf> {
+        if id.is_empty() || id.contains(['/', '\\']) || id.contains("..") {
+            return Err(Error::new(
+                ErrorKind::InvalidInput,
+                format!("invalid session id {:?}", id),
+            ));
+        }
+        Ok(dir.join(format!("{}.json", id)))
+    }
+
+    /// title shown in the session list, the first prompt if never renamed
+    pub fn title(&self) -> String {
+        if !self.name.is_empty() {
+            return self.name.clone();
+        }
+
+        self.conversation
+            .iter()
+            .find_map(|msg| msg.content.as_deref())
+            .and_then(|content| content.lines().next())
+            .map(|line| line.chars().take(60).collect())
+            .unwrap_or_else(|| "(empty)".to_owned())
+    }
+
+    /// write the session to disk, empty sessions are not worth keeping
+    pub fn save(&mut self) -> Result<()> {
+        self.save_to(&Self::dir()?)
+    }
+
+    pub fn save_to(&mut self, dir: &Path) -> Result<()> {
+        if self.conversation.is_empty() {
+            return Ok(());
+        }
+
+        fs::create_dir_all(dir)?;
+        self.updated = Local::now();
+        let content = serde_json::to_string_pretty(self)?;
+        fs::write(Self::path(dir, &self.id)?, content)
+    }
+
+    pub fn load(id: &str) -> Result<Self> {
+        Self::load_from(&Self::dir()?, id)
+    }
+
+    pub fn load_from(dir: &Path, id: &str) -> Result<Self> {
+        let content = fs::read_to_string(Self::path(dir, id)?)?;
+        Ok(serde_json::from_str(&content)?)
+    }
+
+    pub fn delete(id: &str) -> Result<()> {
+        Self::delete_from(&Self::dir()?, id)
+    }
+
+    pub fn delete_from(dir: &Path, id: &str) -> Result<()> {
+        fs::remove_file(Self::path(dir, id)?)
+    }
+
+    /// all saved sessions, most recently updated first
+    pub fn list() -> Result<Vec<Self>> {
+        Self::list_from(&Self::dir()?)
+    }
+
+    pub fn list_from(dir: &Path) -> Result<Vec<Self>> {
+        if !dir.exists() {
+            return Ok(Vec::new());
+        }
+
+        let mut sessions = fs::read_dir(dir)?
+            .filter_map(|entry| entry.ok())
+            .filter(|entry| entry.path().extension().is_some_and(|ext| ext == "json"))
+            .filter_map(|entry| fs::read_to_string(entry.path()).ok())
+            .filter_map(|content| serde_json::from_str::<Self>(&content).ok())
+            .collect::<Vec<_>>();
+        sessions.sort_by(|a, b| b.updated.cmp(&a.updated));
+        Ok(sessions)
+    }
+
+    pub fn latest() -> Result<Option<Self>> {
+        Ok(Self::list()?.into_iter().next())
+    }
+}
+
+impl Default for Session {
+    fn default() -> Self {
+        Self::new()
+    }
+}
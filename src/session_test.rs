@@ -0,0 +1,55 @@
+#[cfg(test)]
+mod tests {
+    use crate::llm::Message;
+    use crate::session::Session;
+    use std::io::ErrorKind;
+
+    #[test]
+    fn session_save_list_delete() {
+        let dir = std::env::temp_dir().join(format!("llmi-test-{}", std::process::id()));
+
+        let mut empty = Session::new();
+        empty.id = "empty".to_owned();
+        empty.save_to(&dir).unwrap();
+
+        let mut session = Session::new();
+        assert_ne!(session.id, Session::new().id);
+        session
+            .conversation
+            .push(Message::user("what is rust?".to_owned()));
+        session
+            .conversation
+            .push(Message::assistant("a language".to_owned()));
+        session.save_to(&dir).unwrap();
+
+        let sessions = Session::list_from(&dir).unwrap();
+        assert_eq!(sessions.len(), 1);
+        assert_eq!(sessions[0].title(), "what is rust?");
+
+        let mut loaded = Session::load_from(&dir, &session.id).unwrap();
+        assert_eq!(loaded.conversation.len(), 2);
+        loaded.name = "rust".to_owned();
+        loaded.save_to(&dir).unwrap();
+        assert_eq!(Session::list_from(&dir).unwrap()[0].title(), "rust");
+
+        Session::delete_from(&dir, &session.id).unwrap();
+        assert!(Session::list_from(&dir).unwrap().is_empty());
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn session_bad_id() {
+        let dir = std::env::temp_dir().join(format!("llmi-test-id-{}", std::process::id()));
+        for id in ["", "../../x", "a/b", "a\\b", ".."] {
+            let err = Session::load_from(&dir, id).unwrap_err();
+            assert_eq!(err.kind(), ErrorKind::InvalidInput, "{:?}", id);
+            assert!(Session::delete_from(&dir, id).is_err());
+        }
+
+        let mut session = Session::new();
+        session.id = "../escape".to_owned();
+        session.conversation.push(Message::user("hi".to_owned()));
+        assert!(session.save_to(&dir).is_err());
+        assert!(!dir.join("../escape.json").exists());
+    }
+}
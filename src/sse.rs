@@ -0,0 +1,24 @@
+use eventsource_stream::{EventStreamError, Eventsource};
+use futures::{Stream, StreamExt};
+
+use crate::error::LLMError;
+
+pub use eventsource_stream::Event as SseEvent;
+
+/// decode a `text/event-stream` body into events. lines split across chunks
+/// and multi-byte characters cut in half are buffered until complete,
+/// comments are skipped and multi-line `data:` fields are joined.
+pub fn decode<S, B, E>(body: S) -> impl Stream<Item = Result<SseEvent, LLMError>>
+where
+    S: Stream<Item = Result<B, E>>,
+    B: AsRef<[u8]>,
+    E: Into<LLMError>,
+{
+    body.eventsource().map(|ev| {
+        ev.map_err(|e| match e {
+            EventStreamError::Transport(e) => e.into(),
+            EventStreamError::Utf8(e) => LLMError::Parse(e.to_string()),
+            EventStreamError::Parser(e) => LLMError::Parse(e.to_string()),
+        })
+    })
+}
@@ -1,6 +1,7 @@
 use std::io::{stdout, Result};
 use std::panic;
 
+use crossterm::event::{DisableMouseCapture, EnableMouseCapture};
 use crossterm::terminal::{disable_raw_mode, EnterAlternateScreen, LeaveAlternateScreen};
 use crossterm::{execute, terminal::enable_raw_mode};
 use ratatui::{backend::Backend, Terminal};
@@ -20,7 +21,7 @@ where
     }
 
     pub fn init(&mut self) -> Result<()> {
-        execute!(stdout(), EnterAlternateScreen)?;
+        execute!(stdout(), EnterAlternateScreen, EnableMouseCapture)?;
         enable_raw_mode()?;
 
         let old = panic::take_hook();
@@ -44,7 +45,7 @@ where
 
     fn reset() -> Result<()> {
         disable_raw_mode()?;
-        execute!(stdout(), LeaveAlternateScreen)?;
+        execute!(stdout(), DisableMouseCapture, LeaveAlternateScreen)?;
         Ok(())
     }
 }
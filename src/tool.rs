@@ -0,0 +1,301 @@
+use serde_json::{json, Value};
+use std::{
+    fs,
+    io::Read,
+    process::{Command, Stdio},
+    sync::{
+        mpsc::{self, RecvTimeoutError, Sender},
+        Arc,
+    },
+    thread,
+    time::{Duration, Instant},
+};
+
+/// output longer than this is cut, the model pays for every byte of it
+const MAX_OUTPUT: usize = 64 * 1024;
+const SHELL_TIMEOUT: Duration = Duration::from_secs(60);
+/// how long output is still read once the command is gone
+const PIPE_GRACE: Duration = Duration::from_secs(2);
+
+/// something a model may ask to run, the result is sent back to it
+pub trait Tool: Send + Sync {
+    fn name(&self) -> &'static str;
+    fn description(&self) -> &'static str;
+    /// json schema of the arguments object
+    fn parameters(&self) -> Value;
+    fn call(&self, args: &Value) -> Result<String, String>;
+}
+
+/// what a backend tells the model about a tool
+#[derive(Debug, Clone, PartialEq)]
+pub struct ToolSpec {
+    pub name: String,
+    pub description: String,
+    pub parameters: Value,
+}
+
+/// the tools offered to the models, by name
+#[derive(Clone, Default)]
+pub struct ToolRegistry {
+    tools: Vec<Arc<dyn Tool>>,
+}
+
+impl ToolRegistry {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// reading files, listing directories and running shell commands
+    pub fn builtin() -> Self {
+        let mut registry = Self::new();
+        registry.register(ReadFile);
+        registry.register(ListDir);
+        registry.register(Shell);
+        registry
+    }
+
+    /// only the tools named in `names`
+    pub fn only(mut self, names: &[String]) -> Self {
+        self.tools
+            .retain(|tool| names.iter().any(|n| n == tool.name()));
+        self
+    }
+
+    /// add `tool`, replacing one of the same name
+    pub fn register(&mut self, tool: impl Tool + 'static) {
+        self.tools.retain(|t| t.name() != tool.name());
+        self.tools.push(Arc::new(tool));
+    }
+
+    pub fn get(&self, name: &str) -> Option<Arc<dyn Tool>> {
+        self.tools.iter().find(|t| t.name() == name).cloned()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.tools.is_empty()
+    }
+
+    pub fn specs(&self) -> Vec<ToolSpec> {
+        self.tools
+            .iter()
+            .map(|tool| ToolSpec {
+                name: tool.name().to_owned(),
+                description: tool.description().to_owned(),
+                parameters: tool.parameters(),
+            })
+            .collect()
+    }
+
+    /// run tool `name` with `arguments` as sent by the model, failures are
+    /// reported back to it as text
+    pub fn call(&self, name: &str, arguments: &str) -> String {
+        let Some(tool) = self.get(name) else {
+            return format!("error: no tool named {}", name);
+        };
+        let args = match arguments.trim() {
+            "" => Value::Object(Default::default()),
+            arguments => match serde_json::from_str(arguments) {
+                Ok(args) => args,
+                Err(e) => return format!("error: invalid arguments: {}", e),
+            },
+        };
+        match tool.call(&args) {
+            Ok(output) => truncate(output),
+            Err(e) => format!("error: {}", e),
+        }
+    }
+}
+
+fn truncate(mut output: String) -> String {
+    if output.len() > MAX_OUTPUT {
+        let mut end = MAX_OUTPUT;
+        while !output.is_char_boundary(end) {
+            end -= 1;
+        }
+        output.truncate(end);
+        output.push_str("\n[output truncated]");
+    }
+    output
+}
+
+fn string_arg<'a>(args: &'a Value, name: &str) -> Result<&'a str, String> {
+    args.get(name)
+        .and_then(|v| v.as_str())
+        .ok_or_else(|| format!("missing argument {}", name))
+}
+
+fn path_schema(description: &str) -> Value {
+    json!({
+        "type": "object",
+        "properties": { "path": { "type": "string", "description": description } },
+        "required": ["path"],
+    })
+}
+
+pub struct ReadFile;
+
+impl Tool for ReadFile {
+    fn name(&self) -> &'static str {
+        "read_file"
+    }
+
+    fn description(&self) -> &'static str {
+        "Read a text file from the local file system."
+    }
+
+    fn parameters(&self) -> Value {
+        path_schema("path of the file, relative to the working directory")
+    }
+
+    fn call(&self, args: &Value) -> Result<String, String> {
+        let path = string_arg(args, "path")?;
+        let fail = |e: String| format!("{}: {}", path, e);
+        // a character more than is kept, so the cut gets noted
+        let mut bytes = Vec::new();
+        fs::File::open(path)
+            .and_then(|f| f.take(MAX_OUTPUT as u64 + 4).read_to_end(&mut bytes))
+            .map_err(|e| fail(e.to_string()))?;
+        match String::from_utf8(bytes) {
+            Ok(text) => Ok(text),
+            // a character cut in half at the limit is no reason to fail
+            Err(e) if e.utf8_error().error_len().is_none() => {
+                let valid = e.utf8_error().valid_up_to();
+                let mut bytes = e.into_bytes();
+                bytes.truncate(valid);
+                Ok(String::from_utf8(bytes).unwrap_or_default())
+            }
+            Err(_) => Err(fail("not utf-8 text".to_owned())),
+        }
+    }
+}
+
+pub struct ListDir;
+
+impl Tool for ListDir {
+    fn name(&self) -> &'static str {
+        "list_dir"
+    }
+
+    fn description(&self) -> &'static str {
+        "List the entries of a local directory, directories end with a slash."
+    }
+
+    fn parameters(&self) -> Value {
+        path_schema("path of the directory, relative to the working directory")
+    }
+
+    fn call(&self, args: &Value) -> Result<String, String> {
+        let path = string_arg(args, "path").unwrap_or(".");
+        let mut entries = fs::read_dir(path)
+            .map_err(|e| format!("{}: {}", path, e))?
+            .filter_map(|entry| entry.ok())
+            .map(|entry| {
+                let name = entry.file_name().to_string_lossy().into_owned();
+                if entry.path().is_dir() {
+                    name + "/"
+                } else {
+                    name
+                }
+            })
+            .collect::<Vec<_>>();
+        entries.sort();
+        Ok(entries.join("\n"))
+    }
+}
+
+pub struct Shell;
+
+/// send what `pipe` yields to `tx` in chunks from a thread of its own, tagged
+/// with `stderr`. the thread ends with the pipe, nobody waits for it.
+fn drain(mut pipe: impl Read + Send + 'static, stderr: bool, tx: Sender<(bool, Vec<u8>)>) {
+    thread::spawn(move || {
+        let mut buf = [0u8; 8192];
+        let mut kept = 0;
+        while let Ok(n @ 1..) = pipe.read(&mut buf) {
+            // keep draining past the limit, the rest is cut anyway
+            if kept <= MAX_OUTPUT {
+                kept += n;
+                if tx.send((stderr, buf[..n].to_vec())).is_err() {
+                    break;
+                }
+            }
+        }
+    });
+}
+
+impl Tool for Shell {
+    fn name(&self) -> &'static str {
+        "shell"
+    }
+
+    fn description(&self) -> &'static str {
+        "Run a command with `sh -c` in the working directory and return its exit status and output."
+    }
+
+    fn parameters(&self) -> Value {
+        json!({
+            "type": "object",
+            "properties": { "command": { "type": "string", "description": "the command line" } },
+            "required": ["command"],
+        })
+    }
+
+    fn call(&self, args: &Value) -> Result<String, String> {
+        let command = string_arg(args, "command")?;
+        let mut cmd = Command::new("sh");
+        cmd.arg("-c")
+            .arg(command)
+            .stdin(Stdio::null())
+            .stdout(Stdio::piped())
+            .stderr(Stdio::piped());
+        // a group of its own, so a timeout kills whatever it started too
+        #[cfg(unix)]
+        std::os::unix::process::CommandExt::process_group(&mut cmd, 0);
+        let mut child = cmd.spawn().map_err(|e| e.to_string())?;
+
+        // drain the pipes while waiting, a full pipe would block the child
+        let (tx, rx) = mpsc::channel();
+        drain(child.stdout.take().unwrap(), false, tx.clone());
+        drain(child.stderr.take().unwrap(), true, tx);
+
+        let started = Instant::now();
+        let mut status = loop {
+            if let Some(status) = child.try_wait().map_err(|e| e.to_string())? {
+                break status.to_string();
+            }
+            if started.elapsed() > SHELL_TIMEOUT {
+                #[cfg(unix)]
+                let _ = Command::new("kill")
+                    .args(["-KILL", "--", &format!("-{}", child.id())])
+                    .status();
+                let _ = child.kill();
+                let _ = child.wait();
+                break format!("killed after {}s", SHELL_TIMEOUT.as_secs());
+            }
+            thread::sleep(Duration::from_millis(20));
+        };
+        // whatever it started in the background may hold the pipes open,
+        // its output is not waited for long
+        let (mut out, mut err) = (Vec::new(), Vec::new());
+        let deadline = Instant::now() + PIPE_GRACE;
+        loop {
+            match rx.recv_timeout(deadline.saturating_duration_since(Instant::now())) {
+                Ok((false, chunk)) => out.extend(chunk),
+                Ok((true, chunk)) => err.extend(chunk),
+                Err(RecvTimeoutError::Disconnected) => break,
+                Err(RecvTimeoutError::Timeout) => {
+                    status.push_str(", output still open, stopped reading");
+                    break;
+                }
+            }
+        }
+
+        let mut output = format!("{}\n", status);
+        output.push_str(&String::from_utf8_lossy(&out));
+        if !err.is_empty() {
+            output.push_str("\n[stderr]\n");
+            output.push_str(&String::from_utf8_lossy(&err));
+        }
+        Ok(output)
+    }
+}
@@ -0,0 +1,66 @@
+#[cfg(test)]
+mod tests {
+    use crate::tool::ToolRegistry;
+
+    #[test]
+    fn tool_registry() {
+        let tools = ToolRegistry::builtin().only(&["read_file".to_owned(), "shell".to_owned()]);
+        let names = tools
+            .specs()
+            .into_iter()
+            .map(|s| s.name)
+            .collect::<Vec<_>>();
+        assert_eq!(names, ["read_file", "shell"]);
+        assert!(ToolRegistry::builtin().only(&[]).is_empty());
+
+        assert_eq!(
+            tools.call("list_dir", "{}"),
+            "error: no tool named list_dir"
+        );
+        assert!(tools
+            .call("read_file", "{not json")
+            .starts_with("error: invalid arguments"));
+        assert_eq!(tools.call("read_file", ""), "error: missing argument path");
+        assert!(tools
+            .call("read_file", r#"{"path":"no/such/file"}"#)
+            .starts_with("error: no/such/file: "));
+    }
+
+    #[test]
+    fn tool_read_and_list() {
+        let tools = ToolRegistry::builtin();
+        let readme = tools.call("read_file", r#"{"path":"README.md"}"#);
+        assert!(readme.starts_with("# llmi"), "{}", readme);
+
+        let listing = tools.call("list_dir", r#"{"path":"tests"}"#);
+        assert_eq!(listing, "fixtures/");
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn tool_shell() {
+        let tools = ToolRegistry::builtin();
+        let output = tools.call("shell", r#"{"command":"echo hi; echo oops >&2; exit 3"}"#);
+        assert_eq!(output, "exit status: 3\nhi\n\n[stderr]\noops\n");
+
+        // a background process keeping stdout open doesn't hold up the result
+        let started = std::time::Instant::now();
+        let output = tools.call("shell", r#"{"command":"echo started; sleep 30 &"}"#);
+        assert!(started.elapsed().as_secs() < 10);
+        assert_eq!(
+            output,
+            "exit status: 0, output still open, stopped reading\nstarted\n"
+        );
+    }
+
+    #[test]
+    fn tool_read_large_file() {
+        let path = std::env::temp_dir().join(format!("llmi-tool-{}.txt", std::process::id()));
+        std::fs::write(&path, "é".repeat(64 * 1024)).unwrap();
+        let args = serde_json::json!({ "path": path }).to_string();
+        let output = ToolRegistry::builtin().call("read_file", &args);
+        assert!(output.ends_with("\n[output truncated]"));
+        assert_eq!(output.len(), 64 * 1024 + "\n[output truncated]".len());
+        std::fs::remove_file(&path).unwrap();
+    }
+}